@@ -0,0 +1,81 @@
+//! The functions shared by the `cargo cpp` binary and its tests.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::{exit, Command};
+
+/// Print the error message, and exit with a failure
+pub fn error(message: &str) -> ! {
+    eprintln!("error: {}", message);
+    exit(1)
+}
+
+fn rustc(args: &[&str]) -> String {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned());
+    match Command::new(&rustc).args(args).output() {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).into_owned()
+        }
+        _ => error(&format!("unable to run `{} {}`", rustc, args.join(" "))),
+    }
+}
+
+/// Set the environment variables which cargo gives to a build script, as
+/// cpp_build and the cc crate rely on them. Like in a build script, the
+/// current directory is the directory of the crate.
+pub fn setup_environment(manifest_dir: &Path, out_dir: &Path, features: &[String]) {
+    env::set_current_dir(manifest_dir)
+        .unwrap_or_else(|e| error(&format!("cannot enter `{}`: {}", manifest_dir.display(), e)));
+    fs::create_dir_all(out_dir)
+        .unwrap_or_else(|e| error(&format!("cannot create `{}`: {}", out_dir.display(), e)));
+    env::set_var("CARGO_MANIFEST_DIR", manifest_dir);
+    env::set_var("OUT_DIR", out_dir);
+    if env::var_os("HOST").is_none() {
+        let version = rustc(&["-vV"]);
+        let host = version
+            .lines()
+            .find_map(|line| line.strip_prefix("host: "))
+            .unwrap_or_else(|| error("unable to find the host triple in `rustc -vV`"));
+        env::set_var("HOST", host);
+    }
+    if env::var_os("TARGET").is_none() {
+        env::set_var("TARGET", env::var_os("HOST").unwrap());
+    }
+    for feature in features {
+        env::set_var(
+            format!("CARGO_FEATURE_{}", feature.to_uppercase().replace('-', "_")),
+            "1",
+        );
+    }
+
+    // The target configuration, used to evaluate the `#[cfg(...)]` attributes
+    let target = env::var("TARGET").unwrap();
+    let mut cfgs: Vec<(String, Vec<String>)> = Vec::new();
+    for line in rustc(&["--print", "cfg", "--target", &target]).lines() {
+        let (name, value) = match line.split_once('=') {
+            Some((name, value)) => (name, Some(value.trim_matches('"'))),
+            None => (line, None),
+        };
+        let index = match cfgs.iter().position(|(n, _)| n == name) {
+            Some(index) => index,
+            None => {
+                cfgs.push((name.to_owned(), Vec::new()));
+                cfgs.len() - 1
+            }
+        };
+        cfgs[index].1.extend(value.map(str::to_owned));
+    }
+    for (name, values) in cfgs {
+        env::set_var(
+            format!("CARGO_CFG_{}", name.to_uppercase()),
+            values.join(","),
+        );
+    }
+    if env::var_os("OPT_LEVEL").is_none() {
+        env::set_var("OPT_LEVEL", "0");
+    }
+    if env::var_os("DEBUG").is_none() {
+        env::set_var("DEBUG", "false");
+    }
+}
@@ -0,0 +1,242 @@
+//! `cargo cpp`: inspect the C++ code embedded in a crate with the `cpp` crate.
+//!
+//! It runs the parser of the `cpp_build` build script over the crate, and can
+//! list the `cpp!` closures and `cpp_class!` classes which it finds, print the
+//! generated C++ code, check its syntax, or write a `compile_commands.json` for
+//! it. This helps understanding why a macro is "not found in the library's
+//! rust-cpp metadata".
+//!
+//! The configuration done in the `build.rs` of the crate is not known, so the
+//! include directories and the definitions need to be given again with `-I`
+//! and `-D` to check the code. The compiler and its flags are taken from the
+//! same environment variables as in the build script (`CXX`, `CXXFLAGS`, ...).
+
+extern crate cargo_cpp;
+extern crate cpp_build;
+
+use cargo_cpp::{error, setup_environment};
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+const USAGE: &str = "\
+Inspect the C++ code embedded with the `cpp` crate
+
+USAGE:
+    cargo cpp <COMMAND> [OPTIONS]
+
+COMMANDS:
+    list                List the cpp! closures and cpp_class! classes of the crate
+    dump                Print the generated C++ code
+    check               Check the syntax of the generated C++ code
+    compile-commands    Write a compile_commands.json for the generated C++ code
+
+OPTIONS:
+    --manifest-path <PATH>    Path to the Cargo.toml of the crate
+    --crate-root <PATH>       Root source file of the crate [default: src/lib.rs or src/main.rs]
+    --out-dir <DIR>           Directory of the generated files [default: target/cargo-cpp]
+    --features <FEATURES>     Comma separated list of the enabled features of the crate
+    -I <DIR>                  Add a directory to the include path
+    -D <NAME[=VALUE]>         Define a preprocessor macro
+    --flag <FLAG>             Add a flag to the compiler command line
+    --mirror-modules          Also write the C++ code of each module, for compile-commands
+    -h, --help                Print this help
+";
+
+#[derive(Default)]
+struct Options {
+    command: String,
+    manifest_path: Option<PathBuf>,
+    crate_root: Option<PathBuf>,
+    out_dir: Option<PathBuf>,
+    features: Vec<String>,
+    includes: Vec<PathBuf>,
+    defines: Vec<(String, Option<String>)>,
+    flags: Vec<String>,
+    mirror_modules: bool,
+}
+
+fn parse_args() -> Options {
+    let mut args = env::args().skip(1).peekable();
+    // When run as `cargo cpp`, the first argument is the name of the subcommand
+    if args.peek().map(String::as_str) == Some("cpp") {
+        args.next();
+    }
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .unwrap_or_else(|| error(&format!("missing value for `{}`", name)))
+        };
+        match arg.as_str() {
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                exit(0);
+            }
+            "--manifest-path" => options.manifest_path = Some(value(&arg).into()),
+            "--crate-root" => options.crate_root = Some(value(&arg).into()),
+            "--out-dir" => options.out_dir = Some(value(&arg).into()),
+            "--features" => options
+                .features
+                .extend(value(&arg).split(',').map(|f| f.trim().to_owned())),
+            "--flag" => options.flags.push(value(&arg)),
+            "--mirror-modules" => options.mirror_modules = true,
+            "-I" => options.includes.push(value(&arg).into()),
+            "-D" => {
+                let define = value(&arg);
+                options.defines.push(match define.split_once('=') {
+                    Some((name, val)) => (name.to_owned(), Some(val.to_owned())),
+                    None => (define, None),
+                });
+            }
+            _ if arg.starts_with("-I") => options.includes.push(arg[2..].into()),
+            _ if arg.starts_with("-D") => {
+                let define = &arg[2..];
+                options.defines.push(match define.split_once('=') {
+                    Some((name, val)) => (name.to_owned(), Some(val.to_owned())),
+                    None => (define.to_owned(), None),
+                });
+            }
+            _ if arg.starts_with('-') => error(&format!("unknown option `{}`", arg)),
+            _ if options.command.is_empty() => options.command = arg,
+            _ => error(&format!("unexpected argument `{}`", arg)),
+        }
+    }
+    if options.command.is_empty() {
+        eprint!("{}", USAGE);
+        exit(1);
+    }
+    options
+}
+
+/// Find the Cargo.toml of the crate in the current directory or its parents
+fn find_manifest() -> PathBuf {
+    let cwd = env::current_dir().unwrap_or_else(|e| error(&e.to_string()));
+    cwd.ancestors()
+        .map(|dir| dir.join("Cargo.toml"))
+        .find(|manifest| manifest.is_file())
+        .unwrap_or_else(|| {
+            error("could not find `Cargo.toml` in the current directory or its parents")
+        })
+}
+
+/// Run rustc with the given arguments, and return its output
+fn config(options: &Options) -> cpp_build::Config {
+    let mut config = cpp_build::Config::new();
+    config
+        .cargo_metadata(false)
+        .fail_on_parse_error(true)
+        .mirror_modules(options.mirror_modules);
+    for include in &options.includes {
+        config.include(include);
+    }
+    for (name, value) in &options.defines {
+        config.define(name, value.as_deref());
+    }
+    for flag in &options.flags {
+        config.flag(flag);
+    }
+    config
+}
+
+fn list(crate_root: &Path) {
+    let extracted = cpp_build::extract(crate_root).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(1)
+    });
+    for module in &extracted.modules {
+        let path = module.path.display();
+        for closure in &module.closures {
+            let captures = closure
+                .captures
+                .iter()
+                .map(|c| {
+                    let mutable = if c.mutable { "mut " } else { "" };
+                    format!("{}{} as \"{}\"", mutable, c.name, c.cpp)
+                })
+                .collect::<Vec<_>>();
+            println!(
+                "{}:{}: cpp!({}[{}] -> {}) {}",
+                path,
+                closure.line,
+                if closure.exception { "try " } else { "" },
+                captures.join(", "),
+                closure.return_type,
+                closure.name
+            );
+        }
+        for class in &module.classes {
+            let mode = if class.pinned {
+                "pinned "
+            } else if class.boxed {
+                "boxed "
+            } else {
+                ""
+            };
+            println!(
+                "{}:{}: cpp_class!({}{} as \"{}\")",
+                path, class.line, mode, class.name, class.cpp
+            );
+        }
+    }
+}
+
+fn main() {
+    let options = parse_args();
+
+    let manifest = options.manifest_path.clone().unwrap_or_else(find_manifest);
+    let manifest_dir = fs::canonicalize(&manifest)
+        .unwrap_or_else(|e| error(&format!("cannot open `{}`: {}", manifest.display(), e)))
+        .parent()
+        .unwrap()
+        .to_owned();
+    // The paths given as arguments are relative to the current directory
+    let cwd = env::current_dir().unwrap_or_else(|e| error(&e.to_string()));
+    let out_dir = match &options.out_dir {
+        Some(dir) => cwd.join(dir),
+        None => manifest_dir.join("target").join("cargo-cpp"),
+    };
+    let crate_root = options.crate_root.as_ref().map(|root| cwd.join(root));
+    setup_environment(&manifest_dir, &out_dir, &options.features);
+
+    let crate_root = crate_root.unwrap_or_else(|| {
+        ["src/lib.rs", "src/main.rs"]
+            .iter()
+            .map(PathBuf::from)
+            .find(|root| root.is_file())
+            .unwrap_or_else(|| error("cannot find the crate root, use `--crate-root`"))
+    });
+
+    match options.command.as_str() {
+        "list" => list(&crate_root),
+        "dump" => {
+            let units = config(&options)
+                .generate_only(&crate_root, out_dir.join("rust_cpp"))
+                .unwrap_or_else(|e| error(&e.to_string()));
+            for unit in &units {
+                if units.len() > 1 {
+                    println!("// {}", unit.display());
+                }
+                match fs::read(unit) {
+                    // Ignore the errors, such as a closed pipe
+                    Ok(code) => drop(io::stdout().write_all(&code)),
+                    Err(e) => error(&format!("cannot read `{}`: {}", unit.display(), e)),
+                }
+            }
+        }
+        "check" => {
+            if !config(&options).check(&crate_root) {
+                exit(1);
+            }
+        }
+        "compile-commands" => {
+            match config(&options).write_compile_commands(&crate_root, &out_dir) {
+                Ok(path) => println!("{}", path.display()),
+                Err(e) => error(&e.to_string()),
+            }
+        }
+        command => error(&format!("unknown command `{}`", command)),
+    }
+}
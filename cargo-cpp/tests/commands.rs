@@ -0,0 +1,76 @@
+//! Run `cargo cpp` on the `test` crate of the repository, and compare what it
+//! finds with `cpp_build::extract`.
+
+extern crate cargo_cpp;
+extern crate cpp_build;
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Once;
+
+fn test_crate() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../test")
+}
+
+fn out_dir() -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join("cargo-cpp")
+}
+
+/// Run `cargo cpp` with the given command on the test crate, and return its
+/// output
+fn cargo_cpp(command: &str) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-cpp"))
+        .args(["cpp", command, "--manifest-path"])
+        .arg(test_crate().join("Cargo.toml"))
+        .arg("--out-dir")
+        .arg(out_dir())
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// The names of the closures found by `cpp_build::extract`, in the same
+/// environment as the one which `cargo cpp` sets up
+fn extracted_closures() -> BTreeSet<String> {
+    static SETUP: Once = Once::new();
+    SETUP.call_once(|| cargo_cpp::setup_environment(&test_crate(), &out_dir(), &[]));
+    let extracted = cpp_build::extract(test_crate().join("src/lib.rs")).unwrap();
+    extracted
+        .modules
+        .iter()
+        .flat_map(|m| &m.closures)
+        .map(|c| c.name.clone())
+        .collect()
+}
+
+#[test]
+fn list() {
+    let output = cargo_cpp("list");
+    let closures = output
+        .lines()
+        .filter(|line| line.contains(": cpp!("))
+        .map(|line| line.rsplit(' ').next().unwrap().to_owned())
+        .collect::<BTreeSet<_>>();
+    assert_eq!(closures, extracted_closures());
+
+    assert!(output.contains("src/lib.rs"));
+    assert!(output.contains(": cpp_class!(A as \"A\")"));
+    assert!(output.contains(": cpp_class!(pinned SelfRef as \"SelfRef\")"));
+    assert!(output.contains(": cpp_class!(boxed Big as \"Big\")"));
+}
+
+#[test]
+fn dump() {
+    let output = cargo_cpp("dump");
+    let closures = extracted_closures();
+    assert!(!closures.is_empty());
+    for name in closures {
+        assert!(output.contains(&format!("{}(", name)), "{}", name);
+    }
+}
@@ -0,0 +1,8 @@
+#[cfg(feature = "runtime")]
+extern crate cpp_build;
+
+fn main() {
+    // Only the runtime contains C++ code
+    #[cfg(feature = "runtime")]
+    cpp_build::build("src/lib.rs");
+}
@@ -70,6 +70,9 @@
 //!
 //! Behind the scene, it uses the `cc` crate.
 //!
+//! The build script tells cargo to run it again when one of the parsed Rust
+//! files, or one of the local headers included by the C++ code, is modified.
+//!
 //! ## Using external libraries
 //!
 //! Most likely you will want to link against external libraries. You need to tell cpp_build
@@ -92,6 +95,18 @@
 //! find them using some external tool such as the `pkg-config` crate, instead of hardcoding
 //! them in the source)
 //!
+//! # Features
+//!
+//! The crate is `no_std`, and only depends on `alloc`. The default `std` feature
+//! catches the panics of the `rust!` callbacks to report them to the C++ code.
+//! Crates without the standard library must disable it with
+//! `default-features = false`. A panic of a `rust!` callback must then abort, as
+//! it cannot unwind through the C++ code: build them with `panic = "abort"`.
+//!
+//! The `runtime` feature provides `CppString` and `CppVector`, with the vectors
+//! of the primitive types. Their C++ code is compiled by the build script of this
+//! crate, so it is opt-in: without it, depending on `cpp` compiles no C++.
+//!
 //! # Limitations
 //!
 //! As with all procedure macro crates we also need to parse Rust source files to
@@ -99,10 +114,19 @@
 //! might not be supported in full. One example is the attributes. Only a limited
 //! number of attributes is supported, namely: `#[path = "..."]` for `mod`
 //! declarations to specify an alternative path to the module file and
-//! `#[cfg(feature = "...")]` for `mod` declarations to conditionally include the
-//! module into the parsing process. Please note that the latter is only supported
-//! in its simplest form: straight-forward `feature = "..."` without any
-//! additional conditions, `cfg!` macros are also not supported at the moment.
+//! `#[cfg(...)]` to conditionally include modules, items, statements or
+//! expressions (and the `cpp!` macros they contain) into the parsing process.
+//! The `cfg` predicates are evaluated from the environment variables that Cargo
+//! gives to the build script: `feature = "..."`, `target_os`, `target_arch`,
+//! `target_pointer_width`, `unix`, `windows`, `debug_assertions`, ... combined
+//! with `all()`, `any()` and `not()`. The build script cannot know whether the
+//! crate is compiled for `test`, so code depending on `test` is always parsed.
+//! `cfg!` and `cfg_attr` are not supported at the moment.
+//!
+//! The `cpp!` and `cpp_class!` macros may be generated by `macro_rules!` macros
+//! defined in the same crate: the build script expands their invocations to find
+//! them. Macros defined in other crates, as well as procedural macros, are not
+//! expanded, so they cannot generate `cpp!` macros.
 //!
 //! Since the cpp code is included within a rust file, the C++ code must obey both
 //! the Rust and the C++ lexing rules. For example, Rust supports nested block comments
@@ -113,6 +137,14 @@
 //! and not have unbalanced delimiters.
 
 #![no_std]
+#![allow(clippy::needless_doctest_main)]
+
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+// The code generated by the macros refers to the `cpp` crate
+extern crate self as cpp;
+use alloc::string::String;
 
 #[macro_use]
 #[allow(unused_imports)]
@@ -143,31 +175,32 @@ macro_rules! __cpp_internal {
     (@expand_rust_macro [$($a:tt)*] $i:ident [$($an:ident : $at:ty as $ac:tt),*] {$($body:tt)*}) => {
         #[allow(non_snake_case)]
         #[allow(unused_unsafe)]
-        #[cfg_attr(feature = "cargo-clippy", allow(clippy::forget_copy))]
-        #[cfg_attr(feature = "cargo-clippy", allow(clippy::forget_ref))]
         #[doc(hidden)]
-        $($a)* unsafe extern "C" fn $i($($an : *const $at),*) {
+        $($a)* unsafe extern "C" fn $i(panic: *mut $crate::RustPanicInfo, $($an : *const $at),*) {
             $(let $an : $at = unsafe { $an.read() };)*
-            (|| { $($body)* })();
+            let result = $crate::__catch_unwind(|| { $($body)* });
             $(::core::mem::forget($an);)*
-
+            if let Err(payload) = result {
+                unsafe { $crate::__report_rust_panic(panic, &*payload) };
+            }
         }
     };
     (@expand_rust_macro [$($a:tt)*] $i:ident [$($an:ident : $at:ty as $ac:tt),*] -> $rt:ty as $rc:tt {$($body:tt)*}) => {
         #[allow(non_snake_case)]
         #[allow(unused_unsafe)]
-        #[cfg_attr(feature = "cargo-clippy", allow(clippy::forget_copy))]
-        #[cfg_attr(feature = "cargo-clippy", allow(clippy::forget_ref))]
         #[doc(hidden)]
-        $($a)* unsafe extern "C" fn $i($($an : *const $at, )* rt : *mut $rt) -> *mut $rt {
+        $($a)* unsafe extern "C" fn $i(panic: *mut $crate::RustPanicInfo, $($an : *const $at, )* rt : *mut $rt) -> *mut $rt {
 
             $(let $an : $at = unsafe { $an.read() };)*
-            {
+            let result = {
                 #[allow(unused_mut)]
                 let mut lambda = || {$($body)*};
-                unsafe { ::core::ptr::write(rt, lambda()) };
-            }
+                $crate::__catch_unwind(|| unsafe { ::core::ptr::write(rt, lambda()) })
+            };
             $(::core::mem::forget($an);)*
+            if let Err(payload) = result {
+                unsafe { $crate::__report_rust_panic(panic, &*payload) };
+            }
             rt
         }
     };
@@ -216,6 +249,51 @@ macro_rules! __cpp_internal {
 /// });
 /// ```
 ///
+/// The size and alignment of the captured variables and of the return type are checked
+/// at compile time against the ones of the C++ types. The build script also writes them,
+/// with the properties of the C++ types, to the `OUT_DIR/rust_cpp/layout.json` report.
+///
+/// ## Strings
+///
+/// A `&str` or a `&[u8]` can be captured as a `rustcpp::str`, which has a
+/// `data()` and a `size()`, and converts to a `std::string`. When compiled
+/// as C++17, a `&str` can also be captured as a `std::string_view`. To pass
+/// an owned string, use `CppString`, and for a `std::vector`, use
+/// `CppVector` (both need the `runtime` feature).
+///
+/// A `std::string` is not relocatable with every standard library (a short
+/// string may point into the object itself), so `CppString` cannot be a
+/// `cpp_class!` over `std::string` itself: it is a `rustcpp::cpp_string`,
+/// owning a `std::string` allocated on the heap, and must be captured as
+/// such. It converts to a `std::string&`.
+///
+/// ```ignore
+/// let name = "World";
+/// cpp!(unsafe [name as "std::string_view"] {
+///     std::cout << "Hello, " << name << std::endl;
+/// });
+/// ```
+///
+/// ## Exceptions
+///
+/// A C++ exception cannot propagate out of the body of a `cpp!` closure: the
+/// closure is `noexcept`, so the exception calls `std::terminate`.
+/// With the `try` keyword, the closure catches the exceptions, and returns a
+/// `Result` whose error is a [`CppException`] containing the message
+/// returned by `std::exception::what()` and the type name of the exception.
+/// The C++ type after `as` is the type of the value returned on success.
+///
+/// ```ignore
+/// let r: Result<i32, cpp::CppException> = cpp!(unsafe try [y as "int32_t"]
+///         -> Result<i32, cpp::CppException> as "int32_t" {
+///     if (y < 0)
+///         throw std::invalid_argument("negative value");
+///     return y * 2;
+/// });
+/// ```
+///
+/// Without a return type, a `try` closure returns a `Result<(), CppException>`.
+///
 /// ## rust! pseudo-macro
 ///
 /// The `cpp!` macro can contain, in the C++ code, a `rust!` sub-macro, which allows
@@ -248,6 +326,13 @@ macro_rules! __cpp_internal {
 ///      $(-> $ret_rust_type:ty as $rust_c_type:tt)* {$($body:tt)*})
 /// ```
 /// `uniq_ident` is a unique identifier which will be used to name the `extern` function
+///
+/// A panic in the body of a `rust!` macro cannot unwind through the C++ code:
+/// it is caught, and by default the process is aborted with a message. The
+/// build script can instead have it thrown as a `rustcpp::rust_panic` C++
+/// exception, whose `what()` is the panic message, with
+/// `cpp_build::Config::panic_policy`. Catching the panic requires the `std`
+/// feature of this crate, which is enabled by default.
 #[macro_export]
 macro_rules! cpp {
     // raw text inclusion
@@ -266,10 +351,223 @@ macro_rules! cpp {
         }
     };
 
+    // inline closure translating C++ exceptions
+    (try [$($captures:tt)*] $($rest:tt)*) => {
+        {
+            $crate::__cpp_internal!{ @find_rust_macro [] $($rest)*}
+            #[allow(unused)]
+            #[derive($crate::__cpp_internal_closure)]
+            enum CppClosureInput {
+                Input = (stringify!(try [$($captures)*] $($rest)*), 0).1
+            }
+            __cpp_closure_impl![$($captures)*]
+        }
+    };
+
     // wrap unsafe
     (unsafe $($tail:tt)*) => { unsafe { cpp!($($tail)*) } };
 }
 
+/// A C++ exception caught by a `cpp!(try ...)` closure.
+///
+/// See the documentation of the [`cpp!`] macro.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CppException {
+    what: String,
+    type_name: String,
+}
+
+impl CppException {
+    /// The message returned by `std::exception::what()`, or a generic message
+    /// if the exception does not inherit from `std::exception`.
+    pub fn what(&self) -> &str {
+        &self.what
+    }
+
+    /// The name of the dynamic type of the exception (demangled when the
+    /// compiler allows it), or an empty string if the exception does not
+    /// inherit from `std::exception`.
+    pub fn type_name(&self) -> &str {
+        &self.type_name
+    }
+}
+
+impl core::fmt::Display for CppException {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        if self.type_name.is_empty() {
+            write!(f, "C++ exception: {}", self.what)
+        } else {
+            write!(f, "C++ exception {}: {}", self.type_name, self.what)
+        }
+    }
+}
+
+/// Called from the C++ code of a `cpp!(try ...)` closure to report the
+/// exception it caught.
+#[doc(hidden)]
+pub unsafe extern "C" fn __set_cpp_exception(
+    exception: *mut Option<CppException>,
+    what: *const core::ffi::c_char,
+    type_name: *const core::ffi::c_char,
+) {
+    let to_string = |s: *const core::ffi::c_char| {
+        String::from_utf8_lossy(core::ffi::CStr::from_ptr(s).to_bytes()).into_owned()
+    };
+    *exception = Some(CppException {
+        what: to_string(what),
+        type_name: to_string(type_name),
+    });
+}
+
+/// Filled by a `rust!` callback whose Rust code panicked. This is the
+/// `rustcpp::rust_panic_info` C++ structure, of which only the first member is
+/// accessed from Rust.
+#[doc(hidden)]
+#[repr(C)]
+pub struct RustPanicInfo {
+    set: unsafe extern "C" fn(*mut RustPanicInfo, *const u8, usize),
+}
+
+/// Run the Rust code of a `rust!` callback, catching its panics so that
+/// they do not unwind into the C++ code. Without the `std` feature, the
+/// panics cannot be caught and the code is simply called.
+#[doc(hidden)]
+pub fn __catch_unwind<R>(
+    f: impl FnOnce() -> R,
+) -> Result<R, alloc::boxed::Box<dyn core::any::Any + Send>> {
+    #[cfg(feature = "std")]
+    {
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(f))
+    }
+    #[cfg(not(feature = "std"))]
+    {
+        Ok(f())
+    }
+}
+
+/// Called by a `rust!` callback to report to the C++ code that it panicked.
+#[doc(hidden)]
+pub unsafe fn __report_rust_panic(info: *mut RustPanicInfo, payload: &(dyn core::any::Any + Send)) {
+    let message = if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.as_str()
+    } else {
+        "Box<dyn Any>"
+    };
+    ((*info).set)(info, message.as_ptr(), message.len());
+}
+
+#[doc(hidden)]
+pub type DebugCallback = unsafe extern "C" fn(*mut core::ffi::c_void, *const u8, usize);
+
+/// The `rustcpp::str` C++ structure: a `&str` or a `&[u8]` captured as a
+/// `rustcpp::str` or a `std::string_view` is converted to it before the call,
+/// as the layout of a Rust slice reference is not specified.
+#[doc(hidden)]
+#[repr(C)]
+pub struct __Str {
+    ptr: *const u8,
+    len: usize,
+}
+
+impl From<&str> for __Str {
+    fn from(s: &str) -> Self {
+        s.as_bytes().into()
+    }
+}
+
+impl From<&[u8]> for __Str {
+    fn from(s: &[u8]) -> Self {
+        __Str {
+            ptr: s.as_ptr(),
+            len: s.len(),
+        }
+    }
+}
+
+/// The `Box` returned by the methods of the `pinned` `cpp_class!`, which
+/// must also be reachable from `no_std` crates
+#[doc(hidden)]
+pub use alloc::boxed::Box as __Box;
+
+/// Allocate a pinned box for a `pinned` `cpp_class!`, in which `init`
+/// constructs the C++ object in place.
+#[doc(hidden)]
+pub unsafe fn __pinned_box<T>(init: impl FnOnce(*mut T)) -> core::pin::Pin<alloc::boxed::Box<T>> {
+    let mut slot = alloc::boxed::Box::new(core::mem::MaybeUninit::<T>::uninit());
+    init(slot.as_mut_ptr());
+    core::pin::Pin::new_unchecked(alloc::boxed::Box::from_raw(
+        alloc::boxed::Box::into_raw(slot) as *mut T,
+    ))
+}
+
+/// Implementation of `Debug` for the `cpp_class!` with `#[derive(Debug)]`:
+/// `call` gives the callback and its context to the C++ helper, which calls
+/// it with the text of the object.
+#[doc(hidden)]
+pub fn __cpp_debug(
+    f: &mut core::fmt::Formatter,
+    call: impl FnOnce(*mut core::ffi::c_void, DebugCallback),
+) -> core::fmt::Result {
+    struct Context<'a, 'b> {
+        f: &'a mut core::fmt::Formatter<'b>,
+        result: core::fmt::Result,
+    }
+    unsafe extern "C" fn callback(context: *mut core::ffi::c_void, data: *const u8, len: usize) {
+        let context = &mut *(context as *mut Context);
+        let text = core::slice::from_raw_parts(data, len);
+        context.result = context.f.write_str(&String::from_utf8_lossy(text));
+    }
+    let mut context = Context { f, result: Ok(()) };
+    call(
+        &mut context as *mut Context as *mut core::ffi::c_void,
+        callback,
+    );
+    context.result
+}
+
+/// The base types of the `cpp_class!` aligned to 8 bytes or more.
+#[doc(hidden)]
+pub mod __align {
+    macro_rules! aligned_types {
+        ($($name:ident = $align:literal),*) => {$(
+            #[repr(C, align($align))]
+            #[derive(Clone, Copy, PartialEq, Eq)]
+            pub struct $name([u8; $align]);
+        )*};
+    }
+    aligned_types!(
+        Align8 = 8,
+        Align16 = 16,
+        Align32 = 32,
+        Align64 = 64,
+        Align128 = 128,
+        Align256 = 256,
+        Align512 = 512,
+        Align1024 = 1024,
+        Align2048 = 2048,
+        Align4096 = 4096,
+        Align8192 = 8192,
+        Align16384 = 16384,
+        Align32768 = 32768,
+        Align65536 = 65536,
+        Align131072 = 131072,
+        Align262144 = 262144,
+        Align524288 = 524288,
+        Align1048576 = 1048576,
+        Align2097152 = 2097152,
+        Align4194304 = 4194304,
+        Align8388608 = 8388608,
+        Align16777216 = 16777216,
+        Align33554432 = 33554432,
+        Align67108864 = 67108864,
+        Align134217728 = 134217728,
+        Align268435456 = 268435456,
+        Align536870912 = 536870912
+    );
+}
+
 #[doc(hidden)]
 pub trait CppTrait {
     type BaseType;
@@ -317,6 +615,60 @@ pub trait CppTrait {
 ///   be called twice. Note that it will never return `None`.
 /// * The trait `Ord` can also be specified when the semantics of the `operator<` corresponds
 ///   to a total order
+/// * The trait `Debug` will write the text streamed by the C++ `operator<<` to a
+///   `std::ostream`. If the type has no such operator, its C++ name and address are
+///   written instead.
+/// * The trait `Hash` will feed the value computed by `std::hash<T>` to the hasher. Add
+///   `Eq` as well to use the type as a key of a `HashMap`.
+/// * The operator traits `Add`, `Sub`, `Mul`, `Div`, `Rem`, `BitAnd`, `BitOr`, `BitXor`,
+///   their `...Assign` variants and `Neg` will call the corresponding C++ operator. They
+///   are implemented for both the type and its references, the operands and the result
+///   have the type of the class.
+/// * The trait `VectorElement` lets the class be stored in a `CppVector`, which needs the
+///   `runtime` feature. A pinned or boxed class cannot derive it.
+///
+/// The operators involving other types are declared with a `#[cpp(...)]` attribute:
+///
+/// * `#[cpp(index(usize as "size_t") -> f64 as "double")]` implements `Index<usize>` with
+///   an `Output` of `f64`, by calling `operator[]` with a `size_t`. `index_mut` implements
+///   `IndexMut` as well. The C++ operator must return a reference to a `double`. Each index
+///   type may only be declared once.
+/// * `#[cpp(deref -> Target as "Target")]` implements `Deref` with `Target` as target, by
+///   calling the unary `operator*`, which must return a reference to a `Target`.
+///   `deref_mut` implements `DerefMut` as well.
+///
+/// Like for the arguments of the member functions, the size and alignment of the Rust
+/// types are checked against the ones of the C++ types.
+///
+/// ```ignore
+/// cpp_class!(
+///     #[derive(Add, Sub, Neg, AddAssign)]
+///     #[cpp(index_mut(usize as "size_t") -> f64 as "double")]
+///     pub unsafe struct Vec3 as "Vec3"
+/// );
+/// ```
+///
+/// ## Member functions
+///
+/// The member functions of the C++ class can be declared between braces after the C++
+/// name. Each argument and the return value is given with its Rust type and the
+/// corresponding C++ type, and their size and alignment are checked at compile time,
+/// like the captures of the `cpp!` closures. The arguments are passed to C++ by
+/// const reference. A function without a `self` parameter calls a static member function.
+///
+/// A declaration without the `fn` keyword and without return type is a constructor: it
+/// becomes an associated function returning `Self`, constructed by calling the C++
+/// constructor with these arguments. A compilation error is reported if the class has no
+/// such constructor, as determined by `std::is_constructible`.
+///
+/// ```ignore
+/// cpp_class!(pub unsafe struct MyClass as "MyClass" {
+///     pub new(a: i32 as "int", b: i32 as "int");
+///     pub fn multiply(&self) -> i32 as "int";
+///     pub fn set_values(&mut self, a: i32 as "int", b: i32 as "int");
+///     pub fn create(value: i32 as "int") -> MyClass as "MyClass";
+/// });
+/// ```
 ///
 /// ## Safety Warning
 ///
@@ -325,7 +677,7 @@ pub trait CppTrait {
 /// easy to break.
 ///
 /// A notable restriction is that this macro only works if the C++ class is
-/// relocatable.
+/// relocatable, unless it is declared as `pinned`.
 ///
 /// ## Relocatable classes
 ///
@@ -334,18 +686,109 @@ pub trait CppTrait {
 /// safe Rust is allowed to move your types around.
 ///
 /// Most C++ types which do not contain self-references will be compatible,
-/// although this property cannot be statically checked by `rust-cpp`.
-/// All types that satisfy `std::is_trivially_copyable` are compatible.
-/// Maybe future version of the C++ standard would allow a comile-time check:
-/// [P1144](http://www.open-std.org/jtc1/sc22/wg21/docs/papers/2019/p1144r4.html)
+/// although this property cannot be fully checked by `rust-cpp`.
+/// All types that satisfy `std::is_trivially_copyable` are compatible, as well as the
+/// types that the compiler reports as trivially relocatable, as proposed by
+/// [P1144](http://www.open-std.org/jtc1/sc22/wg21/docs/papers/2019/p1144r4.html).
 ///
 /// Unfortunately, as the STL often uses internal self-references for
 /// optimization purposes, such as the small-string optimization, this disallows
 /// most std:: classes.
 /// But `std::unique_ptr<T>` and `std::shared_ptr<T>` works.
 ///
+/// A warning is reported for the classes which cannot be moved at all, because
+/// they have neither a copy nor a move constructor. Whether a class is known to be
+/// relocatable is reported in the `layout.json` file of the build script. If a class
+/// can be moved with `memcpy`, this can be declared by specializing the
+/// `rustcpp::is_relocatable` trait in a `cpp!` block:
+///
+/// ```ignore
+/// cpp!{{
+///     namespace rustcpp {
+///         template<> struct is_relocatable<MyClass> : std::true_type {};
+///     }
+/// }}
+/// ```
+///
+/// ## Pinned classes
+///
+/// The classes which are not relocatable can be wrapped with the `pinned` keyword:
+/// `cpp_class!(pinned unsafe struct MyList as "std::list<int>")`. The Rust struct
+/// does not implement `Unpin`, and it is never moved: the objects are constructed in
+/// place on the heap and returned as a `Pin<Box<Self>>`. Instead of the `Default` and
+/// `Clone` traits, the following methods are implemented when the C++ class supports
+/// the corresponding operation:
+///
+/// * `default_boxed()` calls the default constructor.
+/// * `clone_boxed(&self)` calls the copy constructor.
+/// * `move_boxed(self: Pin<&mut Self>)` calls the move constructor.
+/// * `assign(self: Pin<&mut Self>, other: &Self)` calls the copy assignment operator.
+/// * `move_assign(self: Pin<&mut Self>, other: Pin<&mut Self>)` calls the move
+///   assignment operator.
+///
+/// The declared constructors also return a `Pin<Box<Self>>`, and the member functions
+/// taking `&mut self` take a `Pin<&mut Self>` instead. A pinned class cannot derive
+/// `VectorElement`, and it must not be returned by value from a `cpp!` closure.
+///
+/// ## Boxed classes
+///
+/// With the `boxed` keyword, `cpp_class!(boxed unsafe struct MyClass as "MyClass")`, the
+/// C++ object is allocated on the heap and the Rust struct is an owning pointer to it,
+/// which is always relocatable. Its layout does not depend on the one of the C++ class,
+/// which is useful for the classes whose size changes between versions of a library.
+///
+/// The C++ type of the Rust struct is `rustcpp::boxed<MyClass>`, which converts to a
+/// `MyClass&` and can be constructed from a `MyClass`. It must be used for the captures
+/// and return values of the `cpp!` closures and of the member functions:
+///
+/// ```ignore
+/// cpp_class!(boxed unsafe struct MyClass as "MyClass");
+/// let obj = unsafe {
+///     cpp!([] -> MyClass as "rustcpp::boxed<MyClass>" { return MyClass(1, 2); })
+/// };
+/// let value = unsafe {
+///     cpp!([obj as "rustcpp::boxed<MyClass>"] -> i32 as "int" { return obj->value(); })
+/// };
+/// ```
+///
+/// The `Default` and `Clone` traits allocate a new object with the default and copy
+/// constructor of the C++ class. A boxed class is never `Copy`, and it cannot derive
+/// `VectorElement`.
+///
+/// ## Alignment
+///
+/// The Rust struct has the size and alignment of the C++ class, including
+/// over-aligned classes such as `alignas(32)` structures or SIMD types.
+///
 #[macro_export]
 macro_rules! cpp_class {
+    ($(#[$($attrs:tt)*])* pinned unsafe struct $name:ident as $type:literal $({ $($members:tt)* })?) => {
+        $crate::__cpp_class_internal!{@parse [ $(#[$($attrs)*])* ] [] [pinned unsafe struct $name as $type $({ $($members)* })?] }
+    };
+    ($(#[$($attrs:tt)*])* pub pinned unsafe struct $name:ident as $type:literal $({ $($members:tt)* })?) => {
+        $crate::__cpp_class_internal!{@parse [ $(#[$($attrs)*])* ] [pub] [pinned unsafe struct $name as $type $({ $($members)* })?] }
+    };
+    ($(#[$($attrs:tt)*])* pub($($pub:tt)*) pinned unsafe struct $name:ident as $type:literal $({ $($members:tt)* })?) => {
+        $crate::__cpp_class_internal!{@parse [ $(#[$($attrs)*])* ] [pub($($pub)*)] [pinned unsafe struct $name as $type $({ $($members)* })?] }
+    };
+    ($(#[$($attrs:tt)*])* boxed unsafe struct $name:ident as $type:literal $({ $($members:tt)* })?) => {
+        $crate::__cpp_class_internal!{@parse [ $(#[$($attrs)*])* ] [] [boxed unsafe struct $name as $type $({ $($members)* })?] }
+    };
+    ($(#[$($attrs:tt)*])* pub boxed unsafe struct $name:ident as $type:literal $({ $($members:tt)* })?) => {
+        $crate::__cpp_class_internal!{@parse [ $(#[$($attrs)*])* ] [pub] [boxed unsafe struct $name as $type $({ $($members)* })?] }
+    };
+    ($(#[$($attrs:tt)*])* pub($($pub:tt)*) boxed unsafe struct $name:ident as $type:literal $({ $($members:tt)* })?) => {
+        $crate::__cpp_class_internal!{@parse [ $(#[$($attrs)*])* ] [pub($($pub)*)] [boxed unsafe struct $name as $type $({ $($members)* })?] }
+    };
+    ($(#[$($attrs:tt)*])* unsafe struct $name:ident as $type:literal { $($members:tt)* }) => {
+        $crate::__cpp_class_internal!{@parse [ $(#[$($attrs)*])* ] [] [unsafe struct $name as $type { $($members)* }] }
+    };
+    ($(#[$($attrs:tt)*])* pub unsafe struct $name:ident as $type:literal { $($members:tt)* }) => {
+        $crate::__cpp_class_internal!{@parse [ $(#[$($attrs)*])* ] [pub] [unsafe struct $name as $type { $($members)* }] }
+    };
+    ($(#[$($attrs:tt)*])* pub($($pub:tt)*) unsafe struct $name:ident as $type:literal { $($members:tt)* }) => {
+        $crate::__cpp_class_internal!{@parse [ $(#[$($attrs)*])* ] [pub($($pub)*)] [unsafe struct $name as $type { $($members)* }] }
+    };
     ($(#[$($attrs:tt)*])* unsafe struct $name:ident as $type:expr) => {
         $crate::__cpp_class_internal!{@parse [ $(#[$($attrs)*])* ] [] [unsafe struct $name as $type] }
     };
@@ -361,13 +804,34 @@ macro_rules! cpp_class {
 #[doc(hidden)]
 #[macro_export]
 macro_rules! __cpp_class_internal {
-    (@parse [$($attrs:tt)*] [$($vis:tt)*] [unsafe struct $name:ident as $type:expr]) => {
+    (@parse [$($attrs:tt)*] [$($vis:tt)*] [pinned unsafe struct $name:ident as $($type:tt)*]) => {
+        $crate::__cpp_class_internal!{@parse_attributes [ $($attrs)* ] [] [
+            #[derive($crate::__cpp_internal_class)]
+            #[repr(C)]
+            $($vis)* struct $name {
+                _opaque : [<$name as $crate::CppTrait>::BaseType ; <$name as $crate::CppTrait>::ARRAY_SIZE
+                    + (stringify!($($attrs)* $($vis)* pinned unsafe struct $name as $($type)*), 0).1],
+                _pinned : ::core::marker::PhantomPinned,
+            }
+        ]}
+    };
+    (@parse [$($attrs:tt)*] [$($vis:tt)*] [boxed unsafe struct $name:ident as $($type:tt)*]) => {
         $crate::__cpp_class_internal!{@parse_attributes [ $($attrs)* ] [] [
             #[derive($crate::__cpp_internal_class)]
             #[repr(C)]
             $($vis)* struct $name {
                 _opaque : [<$name as $crate::CppTrait>::BaseType ; <$name as $crate::CppTrait>::ARRAY_SIZE
-                    + (stringify!($($attrs)* $($vis)* unsafe struct $name as $type), 0).1]
+                    + (stringify!($($attrs)* $($vis)* boxed unsafe struct $name as $($type)*), 0).1]
+            }
+        ]}
+    };
+    (@parse [$($attrs:tt)*] [$($vis:tt)*] [unsafe struct $name:ident as $($type:tt)*]) => {
+        $crate::__cpp_class_internal!{@parse_attributes [ $($attrs)* ] [] [
+            #[derive($crate::__cpp_internal_class)]
+            #[repr(C)]
+            $($vis)* struct $name {
+                _opaque : [<$name as $crate::CppTrait>::BaseType ; <$name as $crate::CppTrait>::ARRAY_SIZE
+                    + (stringify!($($attrs)* $($vis)* unsafe struct $name as $($type)*), 0).1]
             }
         ]}
     };
@@ -375,6 +839,8 @@ macro_rules! __cpp_class_internal {
     (@parse_attributes [] [$($attributes:tt)*] [$($result:tt)*]) => ( $($attributes)* $($result)* );
     (@parse_attributes [#[derive($($der:ident),*)] $($tail:tt)* ] [$($attributes:tt)*] [$($result:tt)*] )
         => ($crate::__cpp_class_internal!{@parse_derive [$($der),*] @parse_attributes [$($tail)*] [ $($attributes)* ] [ $($result)* ] } );
+    (@parse_attributes [ #[cpp($($op:tt)*)] $($tail:tt)* ] [$($attributes:tt)*] [$($result:tt)*])
+        => ($crate::__cpp_class_internal!{@parse_attributes [$($tail)*] [$($attributes)*] [ $($result)* ] } );
     (@parse_attributes [ #[$m:meta] $($tail:tt)* ] [$($attributes:tt)*] [$($result:tt)*])
         => ($crate::__cpp_class_internal!{@parse_attributes [$($tail)*] [$($attributes)* #[$m] ] [ $($result)* ] } );
 
@@ -391,6 +857,55 @@ macro_rules! __cpp_class_internal {
         => ( $crate::__cpp_class_internal!{@parse_derive [$($tail),*] $($result)*} );
     (@parse_derive [Copy $(,$tail:ident)*] $($result:tt)*)
         => ( $crate::__cpp_class_internal!{@parse_derive [$($tail),*] $($result)*} );
+    (@parse_derive [Debug $(,$tail:ident)*] $($result:tt)*)
+        => ( $crate::__cpp_class_internal!{@parse_derive [$($tail),*] $($result)*} );
+    (@parse_derive [Hash $(,$tail:ident)*] $($result:tt)*)
+        => ( $crate::__cpp_class_internal!{@parse_derive [$($tail),*] $($result)*} );
+    (@parse_derive [Add $(,$tail:ident)*] $($result:tt)*)
+        => ( $crate::__cpp_class_internal!{@parse_derive [$($tail),*] $($result)*} );
+    (@parse_derive [AddAssign $(,$tail:ident)*] $($result:tt)*)
+        => ( $crate::__cpp_class_internal!{@parse_derive [$($tail),*] $($result)*} );
+    (@parse_derive [Sub $(,$tail:ident)*] $($result:tt)*)
+        => ( $crate::__cpp_class_internal!{@parse_derive [$($tail),*] $($result)*} );
+    (@parse_derive [SubAssign $(,$tail:ident)*] $($result:tt)*)
+        => ( $crate::__cpp_class_internal!{@parse_derive [$($tail),*] $($result)*} );
+    (@parse_derive [Mul $(,$tail:ident)*] $($result:tt)*)
+        => ( $crate::__cpp_class_internal!{@parse_derive [$($tail),*] $($result)*} );
+    (@parse_derive [MulAssign $(,$tail:ident)*] $($result:tt)*)
+        => ( $crate::__cpp_class_internal!{@parse_derive [$($tail),*] $($result)*} );
+    (@parse_derive [Div $(,$tail:ident)*] $($result:tt)*)
+        => ( $crate::__cpp_class_internal!{@parse_derive [$($tail),*] $($result)*} );
+    (@parse_derive [DivAssign $(,$tail:ident)*] $($result:tt)*)
+        => ( $crate::__cpp_class_internal!{@parse_derive [$($tail),*] $($result)*} );
+    (@parse_derive [Rem $(,$tail:ident)*] $($result:tt)*)
+        => ( $crate::__cpp_class_internal!{@parse_derive [$($tail),*] $($result)*} );
+    (@parse_derive [RemAssign $(,$tail:ident)*] $($result:tt)*)
+        => ( $crate::__cpp_class_internal!{@parse_derive [$($tail),*] $($result)*} );
+    (@parse_derive [BitAnd $(,$tail:ident)*] $($result:tt)*)
+        => ( $crate::__cpp_class_internal!{@parse_derive [$($tail),*] $($result)*} );
+    (@parse_derive [BitAndAssign $(,$tail:ident)*] $($result:tt)*)
+        => ( $crate::__cpp_class_internal!{@parse_derive [$($tail),*] $($result)*} );
+    (@parse_derive [BitOr $(,$tail:ident)*] $($result:tt)*)
+        => ( $crate::__cpp_class_internal!{@parse_derive [$($tail),*] $($result)*} );
+    (@parse_derive [BitOrAssign $(,$tail:ident)*] $($result:tt)*)
+        => ( $crate::__cpp_class_internal!{@parse_derive [$($tail),*] $($result)*} );
+    (@parse_derive [BitXor $(,$tail:ident)*] $($result:tt)*)
+        => ( $crate::__cpp_class_internal!{@parse_derive [$($tail),*] $($result)*} );
+    (@parse_derive [BitXorAssign $(,$tail:ident)*] $($result:tt)*)
+        => ( $crate::__cpp_class_internal!{@parse_derive [$($tail),*] $($result)*} );
+    (@parse_derive [Neg $(,$tail:ident)*] $($result:tt)*)
+        => ( $crate::__cpp_class_internal!{@parse_derive [$($tail),*] $($result)*} );
+    (@parse_derive [VectorElement $(,$tail:ident)*] $($result:tt)*)
+        => ( $crate::__cpp_class_internal!{@parse_derive [$($tail),*] $($result)*} );
     (@parse_derive [$i:ident $(,$tail:ident)*] @parse_attributes [$($attr:tt)*] [$($attributes:tt)*] [$($result:tt)*] )
         => ( $crate::__cpp_class_internal!{@parse_derive [$($tail),*] @parse_attributes [$($attr)*] [$($attributes)* #[derive($i)] ] [ $($result)* ] } );
 }
+
+#[cfg(feature = "runtime")]
+mod string;
+#[cfg(feature = "runtime")]
+mod vector;
+#[cfg(feature = "runtime")]
+pub use crate::string::CppString;
+#[cfg(feature = "runtime")]
+pub use crate::vector::{CppVector, VectorElement, VectorHelpers};
@@ -0,0 +1,161 @@
+//! Wrapper around the C++ `std::string`.
+
+use alloc::borrow::Cow;
+use alloc::string::{FromUtf8Error, String};
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt;
+use core::str::Utf8Error;
+
+cpp_class!(
+    /// A C++ `std::string`.
+    ///
+    /// It can be created from a `&str`, a `String` or a `&[u8]`. A
+    /// `std::string` is a sequence of bytes, which is not necessarily valid
+    /// UTF-8.
+    ///
+    /// Since a `std::string` cannot always be moved in memory, it is allocated
+    /// on the heap. The C++ type is `rustcpp::cpp_string`, which converts to
+    /// a `std::string&` and can be constructed from a `std::string`:
+    ///
+    /// ```ignore
+    /// let s = CppString::from("hello");
+    /// let len = unsafe {
+    ///     cpp!([s as "rustcpp::cpp_string"] -> usize as "size_t" { return s->size(); })
+    /// };
+    /// assert_eq!(len, 5);
+    /// ```
+    #[derive(PartialEq, PartialOrd, Eq, Ord, VectorElement)]
+    pub unsafe struct CppString as "rustcpp::cpp_string"
+);
+
+impl CppString {
+    /// Create an empty string.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the length of the string, in bytes.
+    pub fn len(&self) -> usize {
+        unsafe {
+            cpp!([self as "const rustcpp::cpp_string*"] -> usize as "size_t" { return (*self)->size(); })
+        }
+    }
+
+    /// Returns true if the string is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the bytes of the string.
+    pub fn as_bytes(&self) -> &[u8] {
+        let data = unsafe {
+            cpp!([self as "const rustcpp::cpp_string*"] -> *const u8 as "const char*" {
+                return (*self)->data();
+            })
+        };
+        unsafe { core::slice::from_raw_parts(data, self.len()) }
+    }
+
+    /// Returns the string as a `&str`, if it is valid UTF-8.
+    pub fn to_str(&self) -> Result<&str, Utf8Error> {
+        core::str::from_utf8(self.as_bytes())
+    }
+
+    /// Returns the string as a `&str`, replacing the invalid UTF-8 sequences
+    /// with `U+FFFD REPLACEMENT CHARACTER`.
+    pub fn to_string_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(self.as_bytes())
+    }
+
+    /// Append bytes to the end of the string.
+    pub fn push_bytes(&mut self, bytes: &[u8]) {
+        unsafe {
+            cpp!([self as "rustcpp::cpp_string*", bytes as "rustcpp::str"] {
+                (*self)->append(bytes.data(), bytes.size());
+            })
+        }
+    }
+
+    /// Append a `&str` to the end of the string.
+    pub fn push_str(&mut self, s: &str) {
+        self.push_bytes(s.as_bytes())
+    }
+
+    /// Remove the content of the string.
+    pub fn clear(&mut self) {
+        unsafe { cpp!([self as "rustcpp::cpp_string*"] { (*self)->clear(); }) }
+    }
+}
+
+impl From<&[u8]> for CppString {
+    fn from(bytes: &[u8]) -> Self {
+        unsafe {
+            cpp!([bytes as "rustcpp::str"] -> CppString as "rustcpp::cpp_string" {
+                return std::string(bytes.data(), bytes.size());
+            })
+        }
+    }
+}
+
+impl From<&str> for CppString {
+    fn from(s: &str) -> Self {
+        s.as_bytes().into()
+    }
+}
+
+impl From<&String> for CppString {
+    fn from(s: &String) -> Self {
+        s.as_bytes().into()
+    }
+}
+
+impl From<String> for CppString {
+    fn from(s: String) -> Self {
+        s.as_bytes().into()
+    }
+}
+
+impl From<&CppString> for Vec<u8> {
+    fn from(s: &CppString) -> Self {
+        s.as_bytes().into()
+    }
+}
+
+impl TryFrom<&CppString> for String {
+    type Error = FromUtf8Error;
+    fn try_from(s: &CppString) -> Result<Self, Self::Error> {
+        String::from_utf8(s.as_bytes().into())
+    }
+}
+
+impl TryFrom<CppString> for String {
+    type Error = FromUtf8Error;
+    fn try_from(s: CppString) -> Result<Self, Self::Error> {
+        String::try_from(&s)
+    }
+}
+
+impl PartialEq<str> for CppString {
+    fn eq(&self, other: &str) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl PartialEq<&str> for CppString {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl fmt::Display for CppString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.to_string_lossy(), f)
+    }
+}
+
+impl fmt::Debug for CppString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.to_string_lossy(), f)
+    }
+}
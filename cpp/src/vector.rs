@@ -0,0 +1,251 @@
+//! Wrapper around the C++ `std::vector<T>`.
+
+use core::ffi::c_void;
+use core::fmt;
+use core::iter::FromIterator;
+use core::marker::PhantomData;
+use core::mem::{ManuallyDrop, MaybeUninit};
+use core::ptr::NonNull;
+
+/// The functions manipulating a `std::vector` of a given element type. This is
+/// the `rustcpp::vector_helpers` C++ structure.
+#[doc(hidden)]
+#[repr(C)]
+pub struct VectorHelpers {
+    create: unsafe extern "C" fn(v: *mut c_void),
+    destroy: unsafe extern "C" fn(v: *mut c_void),
+    copy: unsafe extern "C" fn(src: *const c_void, dest: *mut c_void),
+    len: unsafe extern "C" fn(v: *const c_void) -> usize,
+    data: unsafe extern "C" fn(v: *mut c_void) -> *mut c_void,
+    push: unsafe extern "C" fn(v: *mut c_void, value: *mut c_void),
+    reserve: unsafe extern "C" fn(v: *mut c_void, additional: usize),
+}
+
+/// The types which can be stored in a [`CppVector`]: the types defined with
+/// `cpp_class!` which derive `VectorElement`, and the primitive integer and
+/// floating point types.
+///
+/// # Safety
+///
+/// The helpers must manipulate a `rustcpp::vector` of the C++ type with the
+/// same layout as `Self`.
+pub unsafe trait VectorElement: Sized {
+    #[doc(hidden)]
+    fn vector_helpers() -> &'static VectorHelpers;
+}
+
+macro_rules! primitive_elements {
+    ($($t:ident as $cpp:ident),*) => {
+        crate::cpp! {{ $(RUST_CPP_VECTOR_HELPER($t, $cpp))* }}
+        $(
+            unsafe impl VectorElement for $t {
+                fn vector_helpers() -> &'static VectorHelpers {
+                    extern "C" {
+                        #[link_name = concat!("__cpp_vector_helpers_", stringify!($t))]
+                        fn vector_helpers() -> &'static VectorHelpers;
+                    }
+                    unsafe { vector_helpers() }
+                }
+            }
+        )*
+    };
+}
+
+primitive_elements!(
+    i8 as int8_t,
+    u8 as uint8_t,
+    i16 as int16_t,
+    u16 as uint16_t,
+    i32 as int32_t,
+    u32 as uint32_t,
+    i64 as int64_t,
+    u64 as uint64_t,
+    isize as intptr_t,
+    usize as uintptr_t,
+    f32 as float,
+    f64 as double
+);
+
+/// A C++ `std::vector<T>`.
+///
+/// Like `CppString`, the `std::vector` is allocated on the heap. The C++ type is `rustcpp::vector<T>`, which converts to a
+/// `std::vector<T>&` and can be constructed from a `std::vector<T>`:
+///
+/// ```ignore
+/// let v: CppVector<i32> = (1..=3).collect();
+/// let sum = unsafe {
+///     cpp!([v as "rustcpp::vector<int32_t>"] -> i32 as "int32_t" {
+///         int32_t sum = 0;
+///         for (int32_t x : v.get())
+///             sum += x;
+///         return sum;
+///     })
+/// };
+/// assert_eq!(sum, 6);
+/// ```
+#[repr(C)]
+pub struct CppVector<T: VectorElement> {
+    _opaque: *mut c_void,
+    _marker: PhantomData<T>,
+}
+
+unsafe impl<T: VectorElement + Send> Send for CppVector<T> {}
+unsafe impl<T: VectorElement + Sync> Sync for CppVector<T> {}
+
+impl<T: VectorElement> CppVector<T> {
+    /// Create an empty vector.
+    pub fn new() -> Self {
+        let mut result = MaybeUninit::<Self>::uninit();
+        unsafe {
+            (T::vector_helpers().create)(result.as_mut_ptr() as *mut c_void);
+            result.assume_init()
+        }
+    }
+
+    /// Create an empty vector, with space for at least `capacity` elements.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut result = Self::new();
+        result.reserve(capacity);
+        result
+    }
+
+    fn as_ptr(&self) -> *mut c_void {
+        self as *const Self as *mut c_void
+    }
+
+    /// Returns the number of elements in the vector.
+    pub fn len(&self) -> usize {
+        unsafe { (T::vector_helpers().len)(self.as_ptr()) }
+    }
+
+    /// Returns true if the vector contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reserve space for at least `additional` more elements.
+    pub fn reserve(&mut self, additional: usize) {
+        unsafe { (T::vector_helpers().reserve)(self.as_ptr(), additional) }
+    }
+
+    /// Append an element to the end of the vector.
+    ///
+    /// Aborts if the C++ type is neither movable nor copyable.
+    pub fn push(&mut self, value: T) {
+        let mut value = ManuallyDrop::new(value);
+        unsafe {
+            (T::vector_helpers().push)(self.as_ptr(), &mut *value as *mut T as *mut c_void);
+        }
+    }
+
+    fn data(&self) -> *mut T {
+        let data = unsafe { (T::vector_helpers().data)(self.as_ptr()) } as *mut T;
+        if data.is_null() {
+            NonNull::dangling().as_ptr()
+        } else {
+            data
+        }
+    }
+
+    /// Returns a slice containing the elements of the vector.
+    pub fn as_slice(&self) -> &[T] {
+        unsafe { core::slice::from_raw_parts(self.data(), self.len()) }
+    }
+
+    /// Returns a mutable slice containing the elements of the vector.
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        unsafe { core::slice::from_raw_parts_mut(self.data(), self.len()) }
+    }
+
+    /// Returns an iterator over the elements of the vector.
+    pub fn iter(&self) -> core::slice::Iter<'_, T> {
+        self.as_slice().iter()
+    }
+
+    /// Returns an iterator allowing to modify the elements of the vector.
+    pub fn iter_mut(&mut self) -> core::slice::IterMut<'_, T> {
+        self.as_mut_slice().iter_mut()
+    }
+}
+
+impl<T: VectorElement> Drop for CppVector<T> {
+    fn drop(&mut self) {
+        unsafe { (T::vector_helpers().destroy)(self.as_ptr()) }
+    }
+}
+
+impl<T: VectorElement + Clone> Clone for CppVector<T> {
+    fn clone(&self) -> Self {
+        let mut result = MaybeUninit::<Self>::uninit();
+        unsafe {
+            (T::vector_helpers().copy)(self.as_ptr(), result.as_mut_ptr() as *mut c_void);
+            result.assume_init()
+        }
+    }
+}
+
+impl<T: VectorElement> Default for CppVector<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: VectorElement> FromIterator<T> for CppVector<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut result = Self::new();
+        result.extend(iter);
+        result
+    }
+}
+
+impl<T: VectorElement> Extend<T> for CppVector<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for x in iter {
+            self.push(x);
+        }
+    }
+}
+
+impl<'a, T: VectorElement> IntoIterator for &'a CppVector<T> {
+    type Item = &'a T;
+    type IntoIter = core::slice::Iter<'a, T>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T: VectorElement> IntoIterator for &'a mut CppVector<T> {
+    type Item = &'a mut T;
+    type IntoIter = core::slice::IterMut<'a, T>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<T: VectorElement> AsRef<[T]> for CppVector<T> {
+    fn as_ref(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+
+impl<T: VectorElement> AsMut<[T]> for CppVector<T> {
+    fn as_mut(&mut self) -> &mut [T] {
+        self.as_mut_slice()
+    }
+}
+
+impl<T: VectorElement + PartialEq> PartialEq for CppVector<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl<T: VectorElement + Eq> Eq for CppVector<T> {}
+
+impl<T: VectorElement + fmt::Debug> fmt::Debug for CppVector<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
@@ -0,0 +1,118 @@
+//! Cache of the objects compiled from the generated translation units.
+//!
+//! The objects are stored in `OUT_DIR/rust_cpp/cache`, named after a hash of
+//! the content of the translation unit and of the compiler command line, so
+//! that a translation unit which did not change is not compiled again. The
+//! headers included by a translation unit are tracked with the depfile
+//! generated by the compiler next to the object.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::mem::take;
+use std::path::{Path, PathBuf};
+
+/// Returns the path of the cached object for this translation unit.
+pub fn object_path(cache_dir: &Path, compiler: &cc::Tool, unit: &Path) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    cpp_common::VERSION.hash(&mut hasher);
+    compiler.path().hash(&mut hasher);
+    compiler.args().hash(&mut hasher);
+    fs::read(unit)
+        .expect("Unable to read generated C++ file")
+        .hash(&mut hasher);
+    let extension = if compiler.is_like_msvc() { "obj" } else { "o" };
+    cache_dir.join(format!("{:016x}.{}", hasher.finish(), extension))
+}
+
+/// Returns the path of the depfile stored next to a cached object.
+pub fn depfile_path(object: &Path) -> PathBuf {
+    object.with_extension("d")
+}
+
+/// Returns true if the cached object exists and none of the files it depends
+/// on was modified since it was compiled. Without a depfile (MSVC), we cannot
+/// know whether an included header changed, so the object is never reused.
+pub fn is_up_to_date(object: &Path) -> bool {
+    let modified = |p: &Path| fs::metadata(p).and_then(|m| m.modified()).ok();
+    let object_time = match modified(object) {
+        Some(t) => t,
+        None => return false,
+    };
+    match fs::read_to_string(depfile_path(object)) {
+        Ok(deps) => parse_depfile(&deps)
+            .iter()
+            .all(|dep| modified(dep).map_or(false, |t| t <= object_time)),
+        Err(_) => false,
+    }
+}
+
+/// Remove the cached objects and depfiles which are not in `objects`.
+pub fn remove_unused(cache_dir: &Path, objects: &[PathBuf]) {
+    let entries = match fs::read_dir(cache_dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !objects.iter().any(|o| o.file_stem() == path.file_stem()) {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Parse a depfile in the Makefile format generated by the `-MD` family of
+/// flags, and return the dependencies of the target.
+pub fn parse_depfile(content: &str) -> Vec<PathBuf> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.peek() {
+                Some(' ') | Some('#') => current.push(chars.next().unwrap()),
+                // Line continuation
+                Some('\n') | Some('\r') => {
+                    words.push(take(&mut current));
+                }
+                _ => current.push('\\'),
+            },
+            '$' if chars.peek() == Some(&'$') => {
+                chars.next();
+                current.push('$');
+            }
+            c if c.is_whitespace() => words.push(take(&mut current)),
+            c => current.push(c),
+        }
+    }
+    words.push(current);
+    // The first word is the target, followed by a colon
+    words
+        .into_iter()
+        .filter(|w| !w.is_empty())
+        .skip_while(|w| !w.ends_with(':'))
+        .skip(1)
+        .filter(|w| w != ":")
+        .map(PathBuf::from)
+        .collect()
+}
+
+#[test]
+fn test_parse_depfile() {
+    let deps = parse_depfile(
+        "/out/obj/abc-unit.o: /out/rust_cpp/unit.cpp src/header.h \\\n  /path\\ with\\ spaces/a.h \\\r\n c$$.h\n",
+    );
+    assert_eq!(
+        deps,
+        [
+            PathBuf::from("/out/rust_cpp/unit.cpp"),
+            PathBuf::from("src/header.h"),
+            PathBuf::from("/path with spaces/a.h"),
+            PathBuf::from("c$.h"),
+        ]
+    );
+    assert_eq!(
+        parse_depfile("C:/out/unit.o : C:/out/unit.cpp"),
+        [PathBuf::from("C:/out/unit.cpp")]
+    );
+}
@@ -0,0 +1,287 @@
+//! Evaluation of `#[cfg(...)]` predicates for the module walker.
+//!
+//! Cargo gives build scripts the target configuration through the
+//! `CARGO_CFG_<NAME>` and `CARGO_FEATURE_<NAME>` environment variables, which
+//! is what we use to decide whether an item would be compiled.
+//!
+//! Some predicates cannot be known from a build script: `test` for example,
+//! since the same build script output is used by both `cargo build` and
+//! `cargo test`. Such predicates evaluate to "unknown", and items depending on
+//! them are parsed, so that the C++ code is available in both configurations.
+
+use proc_macro2::{LineColumn, Span};
+use std::env;
+use std::ops::Range;
+use syn::spanned::Spanned;
+use syn::visit::{self, Visit};
+use syn::{Attribute, Lit, Meta, NestedMeta};
+
+/// Returns false if one of the `#[cfg(...)]` attributes is known to evaluate
+/// to false in the current build configuration.
+pub fn is_enabled(attrs: &[Attribute]) -> bool {
+    is_enabled_with(attrs, &|var| env::var(var).ok())
+}
+
+fn is_enabled_with(attrs: &[Attribute], env: &dyn Fn(&str) -> Option<String>) -> bool {
+    attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("cfg"))
+        .all(|attr| match attr.parse_meta() {
+            Ok(Meta::List(list)) if list.nested.len() == 1 => {
+                eval(&list.nested[0], env) != Some(false)
+            }
+            // Let rustc report malformed attributes
+            _ => true,
+        })
+}
+
+/// Returns the byte ranges, within `source`, of the items, statements and
+/// expressions of `file` which are disabled by a `#[cfg(...)]` attribute.
+/// `cpp!` macros within these ranges must not be compiled.
+pub fn disabled_ranges(file: &syn::File, source: &str) -> Vec<Range<usize>> {
+    disabled_ranges_with(file, source, &|var| env::var(var).ok())
+}
+
+fn disabled_ranges_with(
+    file: &syn::File,
+    source: &str,
+    env: &dyn Fn(&str) -> Option<String>,
+) -> Vec<Range<usize>> {
+    let mut visitor = DisabledRanges {
+        source,
+        line_offsets: std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect(),
+        env,
+        ranges: Vec::new(),
+    };
+    visitor.visit_file(file);
+    visitor.ranges
+}
+
+struct DisabledRanges<'a> {
+    source: &'a str,
+    line_offsets: Vec<usize>, // byte offset of the beginning of each line
+    env: &'a dyn Fn(&str) -> Option<String>,
+    ranges: Vec<Range<usize>>,
+}
+
+impl<'a> DisabledRanges<'a> {
+    /// Returns true if the node with these attributes is enabled, otherwise
+    /// record its span as disabled.
+    fn check(&mut self, attrs: &[Attribute], span: Span) -> bool {
+        if is_enabled_with(attrs, self.env) {
+            return true;
+        }
+        let range = self.offset(span.start())..self.offset(span.end());
+        self.ranges.push(range);
+        false
+    }
+
+    /// Convert a proc_macro2 line (1-based) and column (in characters) to a
+    /// byte offset, which is what the C++ macro scanner works with.
+    fn offset(&self, lc: LineColumn) -> usize {
+        let line_start = self.line_offsets[lc.line - 1];
+        self.source[line_start..]
+            .char_indices()
+            .nth(lc.column)
+            .map_or(self.source.len(), |(i, _)| line_start + i)
+    }
+}
+
+// Get the attributes of a syn enum whose variants all have an `attrs` field.
+macro_rules! attrs_of {
+    ($node:expr, $ty:ident; $($variant:ident),*) => {
+        match $node {
+            $(syn::$ty::$variant(x) => &x.attrs[..],)*
+            _ => &[],
+        }
+    };
+}
+
+impl<'a, 'ast> Visit<'ast> for DisabledRanges<'a> {
+    fn visit_item(&mut self, i: &'ast syn::Item) {
+        let attrs = attrs_of!(i, Item; Const, Enum, ExternCrate, Fn, ForeignMod, Impl, Macro,
+            Macro2, Mod, Static, Struct, Trait, TraitAlias, Type, Union, Use);
+        if self.check(attrs, i.span()) {
+            visit::visit_item(self, i);
+        }
+    }
+
+    fn visit_impl_item(&mut self, i: &'ast syn::ImplItem) {
+        let attrs = attrs_of!(i, ImplItem; Const, Method, Type, Macro);
+        if self.check(attrs, i.span()) {
+            visit::visit_impl_item(self, i);
+        }
+    }
+
+    fn visit_trait_item(&mut self, i: &'ast syn::TraitItem) {
+        let attrs = attrs_of!(i, TraitItem; Const, Method, Type, Macro);
+        if self.check(attrs, i.span()) {
+            visit::visit_trait_item(self, i);
+        }
+    }
+
+    fn visit_local(&mut self, i: &'ast syn::Local) {
+        if self.check(&i.attrs, i.span()) {
+            visit::visit_local(self, i);
+        }
+    }
+
+    fn visit_expr(&mut self, i: &'ast syn::Expr) {
+        let attrs = attrs_of!(i, Expr; Array, Assign, AssignOp, Async, Await, Binary, Block, Box,
+            Break, Call, Cast, Closure, Continue, Field, ForLoop, Group, If, Index, Let, Lit, Loop,
+            Macro, Match, MethodCall, Paren, Path, Range, Reference, Repeat, Return, Struct, Try,
+            TryBlock, Tuple, Type, Unary, Unsafe, While, Yield);
+        if self.check(attrs, i.span()) {
+            visit::visit_expr(self, i);
+        }
+    }
+
+    fn visit_arm(&mut self, i: &'ast syn::Arm) {
+        if self.check(&i.attrs, i.span()) {
+            visit::visit_arm(self, i);
+        }
+    }
+}
+
+/// Evaluate a cfg predicate. `None` means that the value cannot be determined.
+fn eval(pred: &NestedMeta, env: &dyn Fn(&str) -> Option<String>) -> Option<bool> {
+    let meta = match pred {
+        NestedMeta::Meta(meta) => meta,
+        NestedMeta::Lit(_) => return None,
+    };
+    let name = meta.path().get_ident()?.to_string();
+    match meta {
+        Meta::List(list) => {
+            let values = list.nested.iter().map(|x| eval(x, env));
+            match name.as_str() {
+                // `all` is false as soon as one value is false, `any` is true as
+                // soon as one is true. Otherwise, any unknown value makes the
+                // result unknown.
+                "all" | "any" => {
+                    let short_circuit = name == "any";
+                    let mut result = Some(!short_circuit);
+                    for v in values {
+                        match v {
+                            Some(v) if v == short_circuit => return Some(v),
+                            Some(_) => {}
+                            None => result = None,
+                        }
+                    }
+                    result
+                }
+                "not" if list.nested.len() == 1 => eval(&list.nested[0], env).map(|v| !v),
+                _ => None,
+            }
+        }
+        Meta::NameValue(nv) => {
+            let value = match nv.lit {
+                Lit::Str(ref s) => s.value(),
+                _ => return None,
+            };
+            if name == "feature" {
+                let var = "CARGO_FEATURE_".to_owned() + &value.to_uppercase().replace('-', "_");
+                return Some(env(&var).is_some());
+            }
+            // If not run by cargo, we know nothing about the target.
+            env("CARGO_CFG_TARGET_ARCH")?;
+            // Values such as `target_feature` or `target_family` may be a
+            // comma separated list.
+            let values = env(&format!("CARGO_CFG_{}", name.to_uppercase()))?;
+            Some(values.split(',').any(|v| v == value))
+        }
+        Meta::Path(_) => match name.as_str() {
+            // Not known by the build script, see the module documentation.
+            "test" | "doc" | "doctest" => None,
+            _ if env("CARGO_CFG_TARGET_ARCH").is_none() => None,
+            _ => Some(env(&format!("CARGO_CFG_{}", name.to_uppercase())).is_some()),
+        },
+    }
+}
+
+#[test]
+fn test_eval_cfg() {
+    let env = |var: &str| match var {
+        "CARGO_CFG_TARGET_ARCH" => Some("x86_64".to_owned()),
+        "CARGO_CFG_TARGET_OS" => Some("linux".to_owned()),
+        "CARGO_CFG_TARGET_POINTER_WIDTH" => Some("64".to_owned()),
+        "CARGO_CFG_TARGET_FAMILY" => Some("unix".to_owned()),
+        "CARGO_CFG_TARGET_FEATURE" => Some("fxsr,sse,sse2".to_owned()),
+        "CARGO_CFG_UNIX" | "CARGO_CFG_DEBUG_ASSERTIONS" => Some(String::new()),
+        "CARGO_FEATURE_QT_6" => Some("1".to_owned()),
+        _ => None,
+    };
+    let check = |s: &str| {
+        let file = syn::parse_file(&format!("{} mod foo;", s)).unwrap();
+        match file.items[0] {
+            syn::Item::Mod(ref m) => is_enabled_with(&m.attrs, &env),
+            _ => unreachable!(),
+        }
+    };
+
+    assert!(check(""));
+    assert!(check("#[cfg(unix)]"));
+    assert!(!check("#[cfg(windows)]"));
+    assert!(check("#[cfg(debug_assertions)]"));
+    assert!(check("#[cfg(feature = \"qt-6\")]"));
+    assert!(!check("#[cfg(feature = \"qt5\")]"));
+    assert!(check("#[cfg(target_os = \"linux\")]"));
+    assert!(!check("#[cfg(target_os = \"macos\")]"));
+    assert!(check("#[cfg(target_pointer_width = \"64\")]"));
+    assert!(check("#[cfg(target_feature = \"sse\")]"));
+    assert!(!check("#[cfg(target_arch = \"arm\")]"));
+    assert!(check("#[cfg(all(unix, target_arch = \"x86_64\"))]"));
+    assert!(!check("#[cfg(all(unix, windows))]"));
+    assert!(check("#[cfg(any(windows, unix))]"));
+    assert!(!check("#[cfg(any(windows, target_os = \"macos\"))]"));
+    assert!(check("#[cfg(not(windows))]"));
+    assert!(!check("#[cfg(not(unix))]"));
+    assert!(!check("#[cfg(unix)] #[cfg(windows)]"));
+
+    // `test` is unknown: both the item and its negation are parsed
+    assert!(check("#[cfg(test)]"));
+    assert!(check("#[cfg(not(test))]"));
+    assert!(check("#[cfg(all(test, unix))]"));
+    assert!(!check("#[cfg(all(test, windows))]"));
+    assert!(check("#[cfg(any(test, windows))]"));
+    assert!(check("#[cfg(any(test, unix))]"));
+}
+
+#[test]
+fn test_disabled_ranges() {
+    let env = |var: &str| match var {
+        "CARGO_CFG_TARGET_ARCH" => Some("x86_64".to_owned()),
+        "CARGO_CFG_UNIX" => Some(String::new()),
+        _ => None,
+    };
+    let source = r#"
+#[cfg(windows)]
+fn foo() { cpp!([] {}) }
+impl Foo {
+    #[cfg(unix)] fn bar() {}
+    #[cfg(windows)] fn baz() {}
+}
+fn main() {
+    #[cfg(windows)]
+    let x = 1;
+    /* é */ #[cfg(windows)] { cpp!([] {}) }
+    match x { #[cfg(windows)] 1 => {}, _ => {} }
+}
+"#;
+    let file = syn::parse_file(source).unwrap();
+    let ranges = disabled_ranges_with(&file, source, &env)
+        .into_iter()
+        .map(|r| &source[r])
+        .collect::<Vec<_>>();
+    assert_eq!(
+        ranges,
+        [
+            "#[cfg(windows)]\nfn foo() { cpp!([] {}) }",
+            "#[cfg(windows)] fn baz() {}",
+            "#[cfg(windows)]\n    let x = 1;",
+            "#[cfg(windows)] { cpp!([] {}) }",
+            "#[cfg(windows)] 1 => {},",
+        ]
+    );
+}
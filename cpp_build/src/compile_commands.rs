@@ -0,0 +1,70 @@
+//! Generation of a `compile_commands.json` compilation database, listing the
+//! command line with which each C++ file is compiled, for the tools such as
+//! clangd and clang-tidy.
+
+use crate::layout::json_string;
+use std::path::{Path, PathBuf};
+
+/// The compiler and its arguments
+pub fn command(compiler: &cc::Tool) -> Vec<String> {
+    let mut command = vec![compiler.path().to_string_lossy().into_owned()];
+    command.extend(
+        compiler
+            .args()
+            .iter()
+            .map(|a| a.to_string_lossy().into_owned()),
+    );
+    command
+}
+
+/// Generate the compilation database for compiling the `files` with the
+/// `command` from the `directory`.
+pub fn generate(command: &[String], directory: &Path, files: &[PathBuf]) -> String {
+    let entries = files
+        .iter()
+        .map(|file| {
+            let file = file.to_string_lossy();
+            let arguments = command
+                .iter()
+                .map(|a| a.as_str())
+                .chain(vec!["-c", &file])
+                .map(json_string)
+                .collect::<Vec<_>>();
+            format!(
+                "  {{\n    \"directory\": {},\n    \"file\": {},\n    \"arguments\": [{}]\n  }}",
+                json_string(&directory.to_string_lossy()),
+                json_string(&file),
+                arguments.join(", ")
+            )
+        })
+        .collect::<Vec<_>>();
+    if entries.is_empty() {
+        "[]\n".to_owned()
+    } else {
+        format!("[\n{}\n]\n", entries.join(",\n"))
+    }
+}
+
+#[test]
+fn test_generate() {
+    let command = ["c++", "-std=c++11", "-I/usr/include/foo bar"]
+        .iter()
+        .map(|a| a.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(generate(&command, Path::new("/crate"), &[]), "[]\n");
+    assert_eq!(
+        generate(
+            &command,
+            Path::new("/crate"),
+            &["/out/cpp_closures.cpp".into()]
+        ),
+        r#"[
+  {
+    "directory": "/crate",
+    "file": "/out/cpp_closures.cpp",
+    "arguments": ["c++", "-std=c++11", "-I/usr/include/foo bar", "-c", "/out/cpp_closures.cpp"]
+  }
+]
+"#
+    );
+}
@@ -0,0 +1,277 @@
+//! Mapping of the C++ compiler diagnostics back to the Rust sources.
+//!
+//! When the generated code fails to compile, the translation units are
+//! compiled again in syntax-only mode to capture the diagnostics of the
+//! compiler. They are parsed (GCC and Clang format: `file:line:col: severity:
+//! message`) and, thanks to the `#line` directives, point into the Rust files.
+//! They are then grouped per `cpp!` or `cpp_class!` invocation and printed as
+//! `cargo:warning` with the offending line of the Rust source.
+
+use crate::layout::parse_line_directive;
+use crate::parser::Module;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+/// A diagnostic of the C++ compiler, with the notes which follow it
+#[derive(Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: u32,
+    pub column: Option<u32>,
+    pub severity: Severity,
+    pub message: String,
+    pub notes: Vec<Diagnostic>,
+}
+
+/// The lines of a Rust file covered by the code of a macro invocation
+struct Invocation {
+    name: &'static str,
+    file: String,
+    lines: std::ops::Range<u32>,
+}
+
+/// Run the compiler in syntax-only mode on the given files, and return the
+/// diagnostics it reports, and whether all the files compiled successfully.
+/// Returns `None` if the compiler is not GCC-like or if it cannot be run.
+pub fn capture(compiler: &cc::Tool, files: &[PathBuf]) -> Option<(Vec<Diagnostic>, bool)> {
+    if compiler.is_like_msvc() {
+        return None;
+    }
+    let mut diagnostics = Vec::new();
+    let mut success = true;
+    for file in files {
+        let mut cmd: Command = compiler.to_command();
+        cmd.arg("-fsyntax-only")
+            .arg("-fdiagnostics-color=never")
+            .arg(file);
+        let output = cmd.output().ok()?;
+        success &= output.status.success();
+        diagnostics.extend(parse(&String::from_utf8_lossy(&output.stderr)));
+    }
+    Some((diagnostics, success))
+}
+
+/// Parse the diagnostics from the output of GCC or Clang. The notes are
+/// attached to the error or warning which precedes them.
+pub fn parse(output: &str) -> Vec<Diagnostic> {
+    lazy_static! {
+        static ref DIAGNOSTIC: Regex =
+            Regex::new(r"^(.+?):(\d+):(?:(\d+):)? (fatal error|error|warning|note): (.*)$")
+                .unwrap();
+    }
+    let mut result: Vec<Diagnostic> = Vec::new();
+    for line in output.lines() {
+        let cap = match DIAGNOSTIC.captures(line.trim_end()) {
+            Some(cap) => cap,
+            None => continue,
+        };
+        let severity = match &cap[4] {
+            "note" => Severity::Note,
+            "warning" => Severity::Warning,
+            _ => Severity::Error,
+        };
+        let diagnostic = Diagnostic {
+            file: cap[1].to_owned(),
+            line: cap[2].parse().unwrap_or(0),
+            column: cap.get(3).and_then(|c| c.as_str().parse().ok()),
+            severity,
+            message: cap[5].to_owned(),
+            notes: Vec::new(),
+        };
+        match result.last_mut() {
+            Some(last) if severity == Severity::Note => last.notes.push(diagnostic),
+            _ => result.push(diagnostic),
+        }
+    }
+    result
+}
+
+/// Format the diagnostics, grouped per macro invocation of the modules, with
+/// the line of the Rust source they point to.
+pub fn report(diagnostics: &[Diagnostic], modules: &[Module]) -> String {
+    let invocations = invocations(modules);
+    let find = |d: &Diagnostic| {
+        invocations
+            .iter()
+            .position(|i| i.file == d.file && i.lines.contains(&d.line))
+    };
+
+    // Keep the order in which the invocations are first found in the output
+    let mut groups: Vec<(Option<usize>, Vec<&Diagnostic>)> = Vec::new();
+    for d in diagnostics {
+        let index = find(d);
+        match groups.iter_mut().find(|(i, _)| *i == index) {
+            Some((_, group)) => group.push(d),
+            None => groups.push((index, vec![d])),
+        }
+    }
+
+    let mut sources = HashMap::new();
+    let mut result = String::new();
+    for (index, group) in groups {
+        match index {
+            Some(index) => {
+                let inv = &invocations[index];
+                let _ = writeln!(
+                    result,
+                    "In the `{}` macro at {}:{}:",
+                    inv.name, inv.file, inv.lines.start
+                );
+            }
+            None => result.push_str("In the generated C++ code:\n"),
+        }
+        for d in group {
+            format_diagnostic(&mut result, d, &mut sources);
+            for note in &d.notes {
+                format_diagnostic(&mut result, note, &mut sources);
+            }
+        }
+        result.push('\n');
+    }
+    result
+}
+
+fn format_diagnostic(
+    result: &mut String,
+    d: &Diagnostic,
+    sources: &mut HashMap<String, Option<String>>,
+) {
+    let severity = match d.severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Note => "note",
+    };
+    let column = d.column.map(|c| format!(":{}", c)).unwrap_or_default();
+    let _ = writeln!(
+        result,
+        "  {}:{}{}: {}: {}",
+        d.file, d.line, column, severity, d.message
+    );
+
+    let source = sources
+        .entry(d.file.clone())
+        .or_insert_with(|| fs::read_to_string(&d.file).ok());
+    let text = match source
+        .as_ref()
+        .and_then(|s| s.lines().nth(d.line.wrapping_sub(1) as usize))
+    {
+        Some(text) => text,
+        None => return,
+    };
+    let number = d.line.to_string();
+    let margin = " ".repeat(number.len());
+    let _ = writeln!(result, "  {} |", margin);
+    let _ = writeln!(result, "  {} | {}", number, text);
+    if let Some(column) = d.column.filter(|c| *c > 0) {
+        let _ = writeln!(
+            result,
+            "  {} | {}^",
+            margin,
+            " ".repeat(column as usize - 1)
+        );
+    }
+}
+
+/// The ranges of the Rust files which contain the C++ code of the closures,
+/// the snippets and the classes
+fn invocations(modules: &[Module]) -> Vec<Invocation> {
+    let mut result = Vec::new();
+    let mut add = |name, code: &str| {
+        let (file, line) = parse_line_directive(code);
+        let count = code.lines().count().max(2) as u32 - 1;
+        result.push(Invocation {
+            name,
+            file,
+            lines: line..line + count,
+        });
+    };
+    for module in modules {
+        for closure in &module.closures {
+            add("cpp!", &closure.body_str);
+        }
+        // The snippets are concatenated, each starting with its #line directive
+        let mut snippet = String::new();
+        for line in module.snippets.lines() {
+            if line.starts_with("#line ") && !snippet.is_empty() {
+                add("cpp!", &snippet);
+                snippet.clear();
+            }
+            if line.starts_with("#line ") || !snippet.is_empty() {
+                snippet.push_str(line);
+                snippet.push('\n');
+            }
+        }
+        if !snippet.is_empty() {
+            add("cpp!", &snippet);
+        }
+        for class in &module.classes {
+            add("cpp_class!", &class.line);
+        }
+    }
+    result
+}
+
+#[test]
+fn test_parse() {
+    let output = "\
+src/lib.rs: In function 'int __cpp_closure_1234(int)':
+src/lib.rs:14:9: error: 'foo' was not declared in this scope
+   14 |         foo(x);
+      |         ^~~
+src/lib.rs:12:5: note: suggested alternative: 'for'
+C:\\src\\lib.rs:3: warning: unused variable
+In file included from unit.cpp:2:
+header.h:1:10: fatal error: missing.h: No such file or directory
+";
+    let diagnostics = parse(output);
+    assert_eq!(diagnostics.len(), 3);
+    assert_eq!(diagnostics[0].file, "src/lib.rs");
+    assert_eq!(diagnostics[0].line, 14);
+    assert_eq!(diagnostics[0].column, Some(9));
+    assert_eq!(diagnostics[0].severity, Severity::Error);
+    assert_eq!(
+        diagnostics[0].message,
+        "'foo' was not declared in this scope"
+    );
+    assert_eq!(diagnostics[0].notes.len(), 1);
+    assert_eq!(diagnostics[0].notes[0].line, 12);
+    assert_eq!(diagnostics[1].file, "C:\\src\\lib.rs");
+    assert_eq!(diagnostics[1].column, None);
+    assert_eq!(diagnostics[1].severity, Severity::Warning);
+    assert_eq!(diagnostics[2].file, "header.h");
+    assert_eq!(diagnostics[2].severity, Severity::Error);
+}
+
+#[test]
+fn test_report() {
+    let mut closure =
+        syn::parse_str::<cpp_common::Closure>("[x as \"int\"] -> i32 as \"int\" {}").unwrap();
+    closure.body_str = "#line 12 \"src/main.rs\"\n    foo(x);\n    return x;\n".to_owned();
+    let module = Module {
+        path: "src/main.rs".into(),
+        closures: vec![closure],
+        ..Default::default()
+    };
+    let diagnostics = parse(
+        "src/main.rs:13:5: error: 'foo' was not declared in this scope\n\
+         unit.cpp:40:1: error: expected ';'\n",
+    );
+    assert_eq!(
+        report(&diagnostics, &[module]),
+        "In the `cpp!` macro at src/main.rs:12:\n\
+         \x20 src/main.rs:13:5: error: 'foo' was not declared in this scope\n\n\
+         In the generated C++ code:\n\
+         \x20 unit.cpp:40:1: error: expected ';'\n\n"
+    );
+}
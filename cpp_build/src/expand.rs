@@ -0,0 +1,419 @@
+//! A minimal `macro_rules!` expander.
+//!
+//! `cpp!` and `cpp_class!` macros generated by a `macro_rules!` macro are not
+//! present verbatim in the source. In order to find them, the build script
+//! expands the invocations of the `macro_rules!` macros defined in the crate,
+//! and scans the result.
+//!
+//! Only the common subset of `macro_rules!` is supported: fragments are parsed
+//! with `syn`, and repetitions are matched greedily without backtracking.
+//! Macros which cannot be expanded are ignored.
+
+use proc_macro2::{Delimiter, Group, Punct, Spacing, TokenStream, TokenTree};
+use std::collections::{HashMap, HashSet};
+use syn::ext::IdentExt;
+use syn::parse::discouraged::Speculative;
+use syn::parse::{ParseStream, Parser, Result};
+
+#[derive(Clone, Debug)]
+enum Binding {
+    Single(TokenStream),
+    Repeated(Vec<Binding>),
+}
+
+type Bindings = HashMap<String, Binding>;
+
+pub struct MacroRules {
+    rules: Vec<(Vec<TokenTree>, Vec<TokenTree>)>, // The matchers and transcribers
+    /// Whether the macro directly contains a `cpp!` or `cpp_class!` invocation
+    pub has_cpp: bool,
+    /// The name of the macros invoked by this macro
+    pub invoked: HashSet<String>,
+}
+
+impl MacroRules {
+    /// Parse the body of a `macro_rules!` definition.
+    /// Returns None if it is not made of `(matcher) => {transcriber};` rules.
+    pub fn parse(body: TokenStream) -> Option<MacroRules> {
+        let mut result = MacroRules {
+            rules: Vec::new(),
+            has_cpp: false,
+            invoked: HashSet::new(),
+        };
+        let mut iter = body.into_iter().peekable();
+        while let Some(matcher) = iter.next() {
+            let matcher = match matcher {
+                TokenTree::Group(g) => g.stream().into_iter().collect(),
+                _ => return None,
+            };
+            match (iter.next(), iter.next()) {
+                (Some(TokenTree::Punct(ref a)), Some(TokenTree::Punct(ref b)))
+                    if a.as_char() == '=' && b.as_char() == '>' => {}
+                _ => return None,
+            }
+            let transcriber = match iter.next() {
+                Some(TokenTree::Group(g)) => g.stream(),
+                _ => return None,
+            };
+            result.find_invoked_macros(transcriber.clone());
+            result
+                .rules
+                .push((matcher, transcriber.into_iter().collect()));
+            match iter.peek() {
+                Some(TokenTree::Punct(p)) if p.as_char() == ';' => {
+                    iter.next();
+                }
+                None => {}
+                _ => return None,
+            }
+        }
+        Some(result)
+    }
+
+    fn find_invoked_macros(&mut self, tokens: TokenStream) {
+        let mut previous = None;
+        for tt in tokens {
+            match tt {
+                TokenTree::Punct(ref p) if p.as_char() == '!' => {
+                    if let Some(TokenTree::Ident(ref i)) = previous {
+                        if i == "cpp" || i == "cpp_class" {
+                            self.has_cpp = true;
+                        } else {
+                            self.invoked.insert(i.to_string());
+                        }
+                    }
+                }
+                TokenTree::Group(ref g) => self.find_invoked_macros(g.stream()),
+                _ => {}
+            }
+            previous = Some(tt);
+        }
+    }
+
+    /// Expand an invocation of this macro, given the tokens within the delimiters.
+    pub fn expand(&self, input: TokenStream) -> Option<TokenStream> {
+        for (matcher, transcriber) in &self.rules {
+            let mut bindings = Bindings::new();
+            let matched = (|input: ParseStream| match_all(matcher, input, &mut bindings))
+                .parse2(input.clone())
+                .is_ok();
+            if matched {
+                let mut result = TokenStream::new();
+                transcribe(transcriber, &bindings, &mut result)?;
+                return Some(result);
+            }
+        }
+        None
+    }
+}
+
+/// Parse the repetition operator following a `$( ... )` group.
+/// Returns the separator, the operator, and the number of tokens used.
+fn repetition_op(tokens: &[TokenTree]) -> Option<(Option<TokenTree>, char, usize)> {
+    let is_op = |tt: Option<&TokenTree>| match tt {
+        Some(TokenTree::Punct(p)) if "*+?".contains(p.as_char()) => Some(p.as_char()),
+        _ => None,
+    };
+    if let Some(op) = is_op(tokens.first()) {
+        return Some((None, op, 1));
+    }
+    let op = is_op(tokens.get(1))?;
+    let sep = match tokens[0] {
+        // The separator is usually joint with the operator
+        TokenTree::Punct(ref p) => TokenTree::Punct(Punct::new(p.as_char(), Spacing::Alone)),
+        ref tt => tt.clone(),
+    };
+    Some((Some(sep), op, 2))
+}
+
+/// Collect the names of the metavariables declared in a matcher.
+fn metavariables(tokens: &[TokenTree], names: &mut Vec<String>) {
+    for (i, tt) in tokens.iter().enumerate() {
+        match tt {
+            TokenTree::Punct(p) if p.as_char() == '$' => {
+                if let Some(TokenTree::Ident(name)) = tokens.get(i + 1) {
+                    names.push(name.to_string());
+                }
+            }
+            TokenTree::Group(g) => {
+                metavariables(&g.stream().into_iter().collect::<Vec<_>>(), names)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Match the beginning of the input against the pattern of a matcher
+fn match_tokens(pattern: &[TokenTree], input: ParseStream, bindings: &mut Bindings) -> Result<()> {
+    let mut i = 0;
+    while i < pattern.len() {
+        match (&pattern[i], pattern.get(i + 1)) {
+            (TokenTree::Punct(p), Some(TokenTree::Ident(name))) if p.as_char() == '$' => {
+                let kind = match (pattern.get(i + 2), pattern.get(i + 3)) {
+                    (Some(TokenTree::Punct(c)), Some(TokenTree::Ident(kind)))
+                        if c.as_char() == ':' =>
+                    {
+                        kind.to_string()
+                    }
+                    _ => return Err(input.error("invalid metavariable")),
+                };
+                let tokens = parse_fragment(&kind, input)?;
+                bindings.insert(name.to_string(), Binding::Single(tokens));
+                i += 4;
+            }
+            (TokenTree::Punct(p), Some(TokenTree::Group(g)))
+                if p.as_char() == '$' && g.delimiter() == Delimiter::Parenthesis =>
+            {
+                let (sep, op, len) = repetition_op(&pattern[i + 2..])
+                    .ok_or_else(|| input.error("invalid repetition"))?;
+                let inner = g.stream().into_iter().collect::<Vec<_>>();
+                let mut repetitions: Vec<Bindings> = Vec::new();
+                loop {
+                    let fork = input.fork();
+                    if !repetitions.is_empty() {
+                        if let Some(ref sep) = sep {
+                            if match_tokens(std::slice::from_ref(sep), &fork, &mut Bindings::new())
+                                .is_err()
+                            {
+                                break;
+                            }
+                        }
+                    }
+                    let mut b = Bindings::new();
+                    if match_tokens(&inner, &fork, &mut b).is_err()
+                        || fork.cursor() == input.cursor()
+                    {
+                        break;
+                    }
+                    input.advance_to(&fork);
+                    repetitions.push(b);
+                    if op == '?' {
+                        break;
+                    }
+                }
+                if op == '+' && repetitions.is_empty() {
+                    return Err(input.error("expected at least one repetition"));
+                }
+                let mut names = Vec::new();
+                metavariables(&inner, &mut names);
+                for name in names {
+                    let values = repetitions
+                        .iter_mut()
+                        .filter_map(|b| b.remove(&name))
+                        .collect();
+                    bindings.insert(name, Binding::Repeated(values));
+                }
+                i += 2 + len;
+            }
+            (TokenTree::Group(g), _) => {
+                let content = input.step(|cursor| {
+                    cursor
+                        .group(g.delimiter())
+                        .map(|(content, _, rest)| (content.token_stream(), rest))
+                        .ok_or_else(|| cursor.error("expected group"))
+                })?;
+                let inner = g.stream().into_iter().collect::<Vec<_>>();
+                (|input: ParseStream| match_all(&inner, input, bindings)).parse2(content)?;
+                i += 1;
+            }
+            (expected, _) => {
+                input.step(|cursor| match cursor.token_tree() {
+                    Some((ref tt, rest)) if tt.to_string() == expected.to_string() => {
+                        Ok(((), rest))
+                    }
+                    _ => Err(cursor.error(format!("expected `{}`", expected))),
+                })?;
+                i += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Like `match_tokens`, but all the input must be matched
+fn match_all(pattern: &[TokenTree], input: ParseStream, bindings: &mut Bindings) -> Result<()> {
+    match_tokens(pattern, input, bindings)?;
+    if !input.is_empty() {
+        return Err(input.error("unexpected token"));
+    }
+    Ok(())
+}
+
+/// Parse a fragment (such as `$x:expr`) and return its tokens
+fn parse_fragment(kind: &str, input: ParseStream) -> Result<TokenStream> {
+    let fork = input.fork();
+    match kind {
+        "ident" => fork.call(syn::Ident::parse_any).map(drop),
+        "tt" => fork.parse::<TokenTree>().map(drop),
+        "literal" => fork.parse::<syn::Lit>().map(drop),
+        "lifetime" => fork.parse::<syn::Lifetime>().map(drop),
+        "expr" => fork.parse::<syn::Expr>().map(drop),
+        "ty" => fork.parse::<syn::Type>().map(drop),
+        "path" => fork.parse::<syn::Path>().map(drop),
+        "pat" => fork.parse::<syn::Pat>().map(drop),
+        "block" => fork.parse::<syn::Block>().map(drop),
+        "stmt" => fork.parse::<syn::Stmt>().map(drop),
+        "item" => fork.parse::<syn::Item>().map(drop),
+        "meta" => fork.parse::<syn::Meta>().map(drop),
+        "vis" => fork.parse::<syn::Visibility>().map(drop),
+        _ => Err(input.error("unsupported fragment")),
+    }?;
+    let end = fork.cursor();
+    let tokens = input.step(|cursor| {
+        let mut tokens = TokenStream::new();
+        let mut rest = *cursor;
+        while rest != end {
+            let (tt, next) = rest.token_tree().unwrap();
+            tokens.extend(Some(tt));
+            rest = next;
+        }
+        Ok((tokens, rest))
+    })?;
+    // Like rustc, keep the expressions and types grouped when they are substituted
+    Ok(match kind {
+        "expr" | "ty" | "path" => TokenTree::Group(Group::new(Delimiter::None, tokens)).into(),
+        _ => tokens,
+    })
+}
+
+fn transcribe(tokens: &[TokenTree], bindings: &Bindings, out: &mut TokenStream) -> Option<()> {
+    let mut i = 0;
+    while i < tokens.len() {
+        match (&tokens[i], tokens.get(i + 1)) {
+            (TokenTree::Punct(p), Some(TokenTree::Ident(name))) if p.as_char() == '$' => {
+                match bindings.get(&name.to_string()) {
+                    Some(Binding::Single(ts)) => out.extend(ts.clone()),
+                    Some(Binding::Repeated(_)) => return None,
+                    // `$crate`, or an unknown metavariable which rustc will report
+                    None => out.extend(tokens[i..i + 2].iter().cloned()),
+                }
+                i += 2;
+            }
+            (TokenTree::Punct(p), Some(TokenTree::Group(g)))
+                if p.as_char() == '$' && g.delimiter() == Delimiter::Parenthesis =>
+            {
+                let (sep, _, len) = repetition_op(&tokens[i + 2..])?;
+                let inner = g.stream().into_iter().collect::<Vec<_>>();
+                let mut names = Vec::new();
+                metavariables(&inner, &mut names);
+                // The repetition count is given by the repeated metavariables it uses
+                let repeated = names
+                    .iter()
+                    .filter_map(|n| match bindings.get(n) {
+                        Some(Binding::Repeated(v)) => Some((n, v)),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+                let count = repeated.first()?.1.len();
+                for k in 0..count {
+                    if k > 0 {
+                        out.extend(sep.clone());
+                    }
+                    let mut b = bindings.clone();
+                    for (name, values) in &repeated {
+                        b.insert(name.to_string(), values.get(k)?.clone());
+                    }
+                    transcribe(&inner, &b, out)?;
+                }
+                i += 2 + len;
+            }
+            (TokenTree::Group(g), _) => {
+                let mut content = TokenStream::new();
+                transcribe(
+                    &g.stream().into_iter().collect::<Vec<_>>(),
+                    bindings,
+                    &mut content,
+                )?;
+                let mut group = Group::new(g.delimiter(), content);
+                group.set_span(g.span());
+                out.extend(Some(TokenTree::Group(group)));
+                i += 1;
+            }
+            (tt, _) => {
+                out.extend(Some(tt.clone()));
+                i += 1;
+            }
+        }
+    }
+    Some(())
+}
+
+/// Returns the names of the macros which may expand to a `cpp!` or `cpp_class!`
+/// macro, directly or through another macro.
+pub fn macros_with_cpp(macros: &HashMap<String, MacroRules>) -> HashSet<String> {
+    let mut result: HashSet<String> = macros
+        .iter()
+        .filter(|(_, m)| m.has_cpp)
+        .map(|(name, _)| name.clone())
+        .collect();
+    loop {
+        let len = result.len();
+        for (name, m) in macros {
+            if m.invoked.iter().any(|i| result.contains(i)) {
+                result.insert(name.clone());
+            }
+        }
+        if result.len() == len {
+            return result;
+        }
+    }
+}
+
+#[test]
+fn test_expand_macro_rules() {
+    let expand = |rules: &str, input: &str| {
+        MacroRules::parse(rules.parse().unwrap())
+            .unwrap()
+            .expand(input.parse().unwrap())
+            .map(|x| x.to_string())
+    };
+
+    assert_eq!(
+        expand(
+            "($name:ident, $field:ident) => { fn $name() { cpp!([] { return $field; }) } }",
+            "foo, bar"
+        )
+        .unwrap(),
+        "fn foo () { cpp ! ([] { return bar ; }) }"
+    );
+    assert_eq!(
+        expand(
+            "() => { none }; ($x:expr) => { one($x) }; ($x:expr, $y:ty) => { two($x as $y) }",
+            "1 + 2, i32"
+        )
+        .unwrap(),
+        "two (1 + 2 as i32)"
+    );
+    assert_eq!(
+        expand(
+            "($($n:ident = $v:literal),* $(,)?) => { $(let $n = $v;)* [$($v),*] }",
+            "a = 1, b = \"x\","
+        )
+        .unwrap(),
+        "let a = 1 ; let b = \"x\" ; [1 , \"x\"]"
+    );
+    assert_eq!(
+        expand(
+            "(struct $n:ident { $($f:ident),+ }) => { $($n.$f)+ }",
+            "struct S { a, b }"
+        )
+        .unwrap(),
+        "S .a S .b"
+    );
+    assert_eq!(
+        expand("($t:tt) => { $crate::x!($t) }", "{ 1 }").unwrap(),
+        "$ crate :: x ! ({ 1 })"
+    );
+    assert_eq!(expand("(a) => {}", "b"), None);
+    assert_eq!(expand("($x:ident) => {}", "a b"), None);
+    assert_eq!(expand("($($x:ident)+) => {}", ""), None);
+
+    let m = MacroRules::parse(
+        "($x:ident) => { foo!($x); cpp!([] {}) }; () => { bar!{} }"
+            .parse()
+            .unwrap(),
+    )
+    .unwrap();
+    assert!(m.has_cpp);
+    assert_eq!(m.invoked.len(), 2);
+}
@@ -0,0 +1,205 @@
+//! Public view of the C++ code extracted from a crate, for the tools which
+//! process it without building it with `cpp_build`.
+
+use crate::layout::parse_line_directive;
+use crate::parser::{self, Error};
+use std::path::{Path, PathBuf};
+
+/// The C++ code of the `cpp!` and `cpp_class!` macros of a crate.
+#[derive(Debug, Clone)]
+pub struct ExtractedCrate {
+    /// The source files containing C++ code, in the order in which they were
+    /// first found
+    pub modules: Vec<ExtractedModule>,
+    /// All the Rust source files which were parsed
+    pub files: Vec<PathBuf>,
+    /// The number of `rust!` callbacks within the closures
+    pub callbacks_count: u32,
+}
+
+/// The C++ code found in one source file of the crate.
+#[derive(Debug, Clone)]
+pub struct ExtractedModule {
+    pub path: PathBuf,
+    pub closures: Vec<ExtractedClosure>,
+    pub classes: Vec<ExtractedClass>,
+    /// The raw C++ code of the `cpp!{{ ... }}` blocks, each starting with a
+    /// `#line` directive
+    pub snippets: String,
+}
+
+/// A `cpp!` closure.
+#[derive(Debug, Clone)]
+pub struct ExtractedClosure {
+    /// The name of the `extern "C"` function generated for the closure
+    pub name: String,
+    /// The line of the body in the source file
+    pub line: u32,
+    pub captures: Vec<ExtractedCapture>,
+    /// The C++ return type, `void` if the closure does not return a value
+    pub return_type: String,
+    /// Whether this is a `cpp!(try ...)` closure
+    pub exception: bool,
+    /// The C++ code of the body, starting with a `#line` directive, and in
+    /// which the `rust!` macros are replaced by calls to the callbacks
+    pub body: String,
+}
+
+/// A variable captured by a `cpp!` closure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtractedCapture {
+    pub name: String,
+    pub cpp: String,
+    pub mutable: bool,
+}
+
+/// A `cpp_class!` declaration.
+#[derive(Debug, Clone)]
+pub struct ExtractedClass {
+    pub name: String,
+    pub cpp: String,
+    /// The line of the macro in the source file
+    pub line: u32,
+    pub pinned: bool,
+    pub boxed: bool,
+}
+
+impl ExtractedCrate {
+    fn new(parser: &parser::Parser) -> ExtractedCrate {
+        ExtractedCrate {
+            modules: parser.modules.iter().map(ExtractedModule::new).collect(),
+            files: parser.files.clone(),
+            callbacks_count: parser.callbacks_count,
+        }
+    }
+}
+
+impl ExtractedModule {
+    fn new(module: &parser::Module) -> ExtractedModule {
+        let closures = module
+            .closures
+            .iter()
+            .map(|c| ExtractedClosure {
+                name: c.sig.extern_name().to_string(),
+                line: parse_line_directive(&c.body_str).1,
+                captures: c
+                    .sig
+                    .captures
+                    .iter()
+                    .map(|capture| ExtractedCapture {
+                        name: capture.name.to_string(),
+                        cpp: capture.cpp.clone(),
+                        mutable: capture.mutable,
+                    })
+                    .collect(),
+                return_type: c.sig.cpp.clone(),
+                exception: c.sig.exception,
+                body: c.body_str.clone(),
+            })
+            .collect();
+        let classes = module
+            .classes
+            .iter()
+            .map(|c| ExtractedClass {
+                name: c.name.to_string(),
+                cpp: c.cpp.clone(),
+                line: parse_line_directive(&c.line).1,
+                pinned: c.pinned,
+                boxed: c.boxed,
+            })
+            .collect();
+        ExtractedModule {
+            path: module.path.clone(),
+            closures,
+            classes,
+            snippets: module.snippets.clone(),
+        }
+    }
+}
+
+/// Extracts the C++ code of the `cpp!` and `cpp_class!` macros of the crate
+/// with a root at the given path, without generating or compiling anything.
+///
+/// The name of the array of the `rust!` callbacks depends on the `OUT_DIR`
+/// environment variable, so an error is returned if it is not set while the
+/// closures contain `rust!` macros.
+pub fn extract<P: AsRef<Path>>(crate_root: P) -> Result<ExtractedCrate, Error> {
+    let mut parser = parser::Parser::default();
+    parser.parse_crate(crate_root)?;
+    Ok(ExtractedCrate::new(&parser))
+}
+
+#[test]
+fn test_extract() {
+    let test_crate = crate::test_crate::TestCrate::new("extract");
+    let root = test_crate.write(
+        "lib.rs",
+        r#"
+cpp!{{
+    #include <string>
+}}
+cpp_class!(pub unsafe struct Str as "std::string");
+fn foo(x: i32) -> i32 {
+    unsafe {
+        cpp!([x as "int"] -> i32 as "int" {
+            return x + 1;
+        })
+    }
+}
+"#,
+    );
+
+    let extracted = extract(&root).unwrap();
+    assert_eq!(extracted.files, vec![root.clone()]);
+    assert_eq!(extracted.callbacks_count, 0);
+    assert_eq!(extracted.modules.len(), 1);
+    let module = &extracted.modules[0];
+    assert_eq!(module.path, root);
+    assert!(module.snippets.contains("#include <string>"));
+
+    assert_eq!(module.closures.len(), 1);
+    let closure = &module.closures[0];
+    assert!(closure.name.starts_with("__cpp_closure_"));
+    assert_eq!(closure.line, 8);
+    assert_eq!(closure.return_type, "int");
+    assert!(!closure.exception);
+    assert!(closure.body.contains("return x + 1;"));
+    assert_eq!(
+        closure.captures,
+        vec![ExtractedCapture {
+            name: "x".to_owned(),
+            cpp: "int".to_owned(),
+            mutable: false,
+        }]
+    );
+
+    assert_eq!(module.classes.len(), 1);
+    assert_eq!(module.classes[0].name, "Str");
+    assert_eq!(module.classes[0].cpp, "std::string");
+    assert_eq!(module.classes[0].line, 5);
+}
+
+#[test]
+fn test_extract_without_out_dir() {
+    // The tests are not run by a build script
+    assert!(std::env::var_os("OUT_DIR").is_none());
+    let test_crate = crate::test_crate::TestCrate::new("extract_without_out_dir");
+    let root = test_crate.write(
+        "lib.rs",
+        r#"
+fn foo() {
+    unsafe {
+        cpp!([] {
+            rust!(foo_callback [] { println!("foo"); });
+        })
+    }
+}
+"#,
+    );
+
+    let err = extract(&root).unwrap_err();
+    assert!(matches!(err, Error::MacroError { .. }));
+    assert!(err
+        .to_string()
+        .contains("the OUT_DIR environment variable must be set to expand the rust! macros"));
+}
@@ -0,0 +1,192 @@
+//! The layout report, written to `OUT_DIR/rust_cpp/layout.json` after the
+//! library is compiled.
+//!
+//! It lists the return type and the captures of each `cpp!` closure, and each
+//! `cpp_class!`, with the size, the alignment and the flags that the C++
+//! compiler computed for the C++ types, as stored in the library metadata:
+//!
+//! ```json
+//! {
+//!   "version": "0.5.4",
+//!   "closures": [
+//!     {
+//!       "name": "__cpp_closure_1234",
+//!       "file": "src/lib.rs",
+//!       "line": 12,
+//!       "return": { "cpp": "int", "size": 4, "align": 4, "flags": ["copy_constructible", ...] },
+//!       "captures": [
+//!         { "name": "x", "mutable": false, "cpp": "int", "size": 4, "align": 4, "flags": [...] }
+//!       ]
+//!     }
+//!   ],
+//!   "classes": [
+//!     { "name": "A", "cpp": "A", "file": "src/lib.rs", "line": 20, "size": 8, "align": 4, "flags": [...] }
+//!   ]
+//! }
+//! ```
+
+use crate::parser::Module;
+use cpp_common::flags;
+use cpp_common::metadata::MetaData;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+/// The names of the flags in the report
+const FLAG_NAMES: [(u32, &str); 10] = [
+    (flags::IS_COPY_CONSTRUCTIBLE, "copy_constructible"),
+    (flags::IS_DEFAULT_CONSTRUCTIBLE, "default_constructible"),
+    (flags::IS_TRIVIALLY_DESTRUCTIBLE, "trivially_destructible"),
+    (flags::IS_TRIVIALLY_COPYABLE, "trivially_copyable"),
+    (
+        flags::IS_TRIVIALLY_DEFAULT_CONSTRUCTIBLE,
+        "trivially_default_constructible",
+    ),
+    (flags::IS_CONSTRUCTIBLE, "constructible"),
+    (flags::IS_MOVE_CONSTRUCTIBLE, "move_constructible"),
+    (flags::IS_COPY_ASSIGNABLE, "copy_assignable"),
+    (flags::IS_MOVE_ASSIGNABLE, "move_assignable"),
+    (flags::IS_TRIVIALLY_RELOCATABLE, "trivially_relocatable"),
+];
+
+/// Generate the JSON report for the closures and classes of the modules.
+pub fn report(modules: &[Module], metadata: &HashMap<u64, Vec<MetaData>>) -> String {
+    let mut closures = Vec::new();
+    let mut seen = HashSet::new();
+    for closure in modules.iter().flat_map(|m| &m.closures) {
+        let hash = closure.sig.name_hash();
+        let data = match metadata.get(&hash) {
+            Some(data) if seen.insert(hash) => data,
+            _ => continue,
+        };
+        let (file, line) = parse_line_directive(&closure.body_str);
+        let captures = closure
+            .sig
+            .captures
+            .iter()
+            .zip(&data[1..])
+            .map(|(capture, meta)| {
+                format!(
+                    "{{ \"name\": {}, \"mutable\": {}, \"cpp\": {}, {} }}",
+                    json_string(&capture.name.to_string()),
+                    capture.mutable,
+                    json_string(&capture.cpp),
+                    layout(meta)
+                )
+            })
+            .collect::<Vec<_>>();
+        closures.push(format!(
+            "    {{\n      \"name\": {},\n      \"file\": {},\n      \"line\": {},\n      \
+             \"return\": {{ \"cpp\": {}, {} }},\n      \"captures\": [{}]\n    }}",
+            json_string(&closure.sig.extern_name().to_string()),
+            json_string(&file),
+            line,
+            json_string(&closure.sig.cpp),
+            layout(&data[0]),
+            if captures.is_empty() {
+                String::new()
+            } else {
+                format!("\n        {}\n      ", captures.join(",\n        "))
+            }
+        ));
+    }
+
+    let mut classes = Vec::new();
+    for class in modules.iter().flat_map(|m| &m.classes) {
+        let data = match metadata.get(&class.name_hash()) {
+            Some(data) => data,
+            None => continue,
+        };
+        let (file, line) = parse_line_directive(&class.line);
+        classes.push(format!(
+            "    {{ \"name\": {}, \"cpp\": {}, \"file\": {}, \"line\": {}, {} }}",
+            json_string(&class.name.to_string()),
+            json_string(&class.cpp),
+            json_string(&file),
+            line,
+            layout(&data[0])
+        ));
+    }
+
+    let list = |items: Vec<String>| {
+        if items.is_empty() {
+            "[]".to_owned()
+        } else {
+            format!("[\n{}\n  ]", items.join(",\n"))
+        }
+    };
+    format!(
+        "{{\n  \"version\": {},\n  \"closures\": {},\n  \"classes\": {}\n}}\n",
+        json_string(cpp_common::VERSION),
+        list(closures),
+        list(classes)
+    )
+}
+
+/// The size, alignment and flags fields of a type
+fn layout(meta: &MetaData) -> String {
+    let flags = FLAG_NAMES
+        .iter()
+        .filter(|(flag, _)| meta.has_flag(*flag))
+        .map(|(_, name)| json_string(name))
+        .collect::<Vec<_>>();
+    format!(
+        "\"size\": {}, \"align\": {}, \"flags\": [{}]",
+        meta.size,
+        meta.align,
+        flags.join(", ")
+    )
+}
+
+/// Extract the file and line of the `#line` directive at the start of the code
+pub fn parse_line_directive(code: &str) -> (String, u32) {
+    let directive = code.lines().next().unwrap_or_default();
+    let rest = match directive.strip_prefix("#line ") {
+        Some(rest) => rest,
+        None => return (String::new(), 0),
+    };
+    let (line, file) = rest.split_once(' ').unwrap_or((rest, ""));
+    let file = file.trim().trim_matches('"').replace("\\\\", "\\");
+    (file, line.parse().unwrap_or(0))
+}
+
+pub fn json_string(s: &str) -> String {
+    let mut result = String::with_capacity(s.len() + 2);
+    result.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(result, "\\u{:04x}", c as u32);
+            }
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+#[test]
+fn test_json_string() {
+    assert_eq!(json_string("int"), "\"int\"");
+    assert_eq!(
+        json_string("const char *\"x\\y\"\n\u{1}"),
+        "\"const char *\\\"x\\\\y\\\"\\n\\u0001\""
+    );
+}
+
+#[test]
+fn test_parse_line_directive() {
+    assert_eq!(
+        parse_line_directive("#line 12 \"src/lib.rs\"\n    return x;"),
+        ("src/lib.rs".to_owned(), 12)
+    );
+    assert_eq!(
+        parse_line_directive("#line 3 \"C:\\\\src\\\\lib.rs\"\n"),
+        ("C:\\src\\lib.rs".to_owned(), 3)
+    );
+    assert_eq!(parse_line_directive("return x;"), (String::new(), 0));
+}
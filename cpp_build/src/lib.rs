@@ -21,12 +21,27 @@ mod strnom;
 
 use cpp_common::*;
 use std::collections::hash_map::{Entry, HashMap};
+use std::collections::HashSet;
 use std::env;
-use std::fs::{create_dir, remove_dir_all, File};
+use std::fs::{self, create_dir_all};
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
 
+mod cache;
+mod cfg;
+mod compile_commands;
+mod diagnostics;
+mod expand;
+mod extract;
+mod layout;
 mod parser;
+#[cfg(test)]
+mod test_crate;
+
+pub use extract::{
+    extract, ExtractedCapture, ExtractedClass, ExtractedClosure, ExtractedCrate, ExtractedModule,
+};
+pub use parser::{Error, Location};
 
 fn warnln_impl(a: &str) {
     for s in a.lines() {
@@ -59,6 +74,21 @@ const INTERNAL_CPP_STRUCTS: &str = r#"
 #include <cstdlib> // For abort
 #include <type_traits>
 #include <utility>
+#include <exception> // For std::exception
+#include <typeinfo> // For typeid
+#include <string>
+#include <memory> // For unique_ptr
+#include <vector>
+#include <sstream> // For ostringstream
+#include <functional> // For std::hash
+#include <cstdio> // For fprintf
+#if __cplusplus >= 201703L || (defined(_MSVC_LANG) && _MSVC_LANG >= 201703L)
+#include <string_view>
+#define RUST_CPP_HAS_STRING_VIEW
+#endif
+#ifdef __GNUC__
+#include <cxxabi.h> // For abi::__cxa_demangle
+#endif
 
 namespace rustcpp {
 
@@ -105,6 +135,270 @@ typename std::enable_if<std::is_default_constructible<T>::value>::type default_h
 template<typename T>
 typename std::enable_if<!std::is_default_constructible<T>::value>::type default_helper(void *)
 { std::abort(); }
+template<typename T>
+typename std::enable_if<std::is_move_constructible<T>::value>::type move_helper(void *src, void *dest)
+{ new (dest) T (std::move(*static_cast<T*>(src))); }
+template<typename T>
+typename std::enable_if<!std::is_move_constructible<T>::value>::type move_helper(void *, void *)
+{ std::abort(); }
+template<typename T>
+typename std::enable_if<std::is_copy_assignable<T>::value>::type copy_assign_helper(const void *src, void *dest)
+{ *static_cast<T*>(dest) = *static_cast<T const*>(src); }
+template<typename T>
+typename std::enable_if<!std::is_copy_assignable<T>::value>::type copy_assign_helper(const void *, void *)
+{ std::abort(); }
+template<typename T>
+typename std::enable_if<std::is_move_assignable<T>::value>::type move_assign_helper(void *src, void *dest)
+{ *static_cast<T*>(dest) = std::move(*static_cast<T*>(src)); }
+template<typename T>
+typename std::enable_if<!std::is_move_assignable<T>::value>::type move_assign_helper(void *, void *)
+{ std::abort(); }
+template<typename T, typename... Args>
+typename std::enable_if<std::is_constructible<T, const Args&...>::value>::type
+construct_helper(void *dest, const Args&... args)
+{ new (dest) T(args...); }
+template<typename T, typename... Args>
+typename std::enable_if<!std::is_constructible<T, const Args&...>::value>::type
+construct_helper(void *, const Args&...)
+{ std::abort(); }
+
+// The C++ side of a `boxed` cpp_class!: the object is allocated on the heap. It is not copyable
+// since T may not be, the Rust `Clone` implementation uses boxed_copy_helper instead.
+template<typename T> class boxed {
+    std::unique_ptr<T> m_ptr;
+public:
+    explicit boxed(T *ptr) : m_ptr(ptr) {}
+    boxed(T &&value) : m_ptr(new T(std::move(value))) {}
+    boxed(const T &value) : m_ptr(new T(value)) {}
+    T &get() { return *m_ptr; }
+    const T &get() const { return *m_ptr; }
+    operator T &() { return *m_ptr; }
+    operator const T &() const { return *m_ptr; }
+    T &operator*() { return *m_ptr; }
+    const T &operator*() const { return *m_ptr; }
+    T *operator->() { return m_ptr.get(); }
+    const T *operator->() const { return m_ptr.get(); }
+};
+
+template<typename T>
+typename std::enable_if<std::is_copy_constructible<T>::value>::type boxed_copy_helper(const void *src, void *dest)
+{ new (dest) boxed<T>(new T(static_cast<boxed<T> const*>(src)->get())); }
+template<typename T>
+typename std::enable_if<!std::is_copy_constructible<T>::value>::type boxed_copy_helper(const void *, void *)
+{ std::abort(); }
+template<typename T>
+typename std::enable_if<std::is_default_constructible<T>::value>::type boxed_default_helper(void *dest)
+{ new (dest) boxed<T>(new T()); }
+template<typename T>
+typename std::enable_if<!std::is_default_constructible<T>::value>::type boxed_default_helper(void *)
+{ std::abort(); }
+template<typename T, typename... Args>
+typename std::enable_if<std::is_constructible<T, const Args&...>::value>::type
+boxed_construct_helper(void *dest, const Args&... args)
+{ new (dest) boxed<T>(new T(args...)); }
+template<typename T, typename... Args>
+typename std::enable_if<!std::is_constructible<T, const Args&...>::value>::type
+boxed_construct_helper(void *, const Args&...)
+{ std::abort(); }
+
+// A Rust `&str` or `&[u8]`, which can be captured as `rustcpp::str`. It is
+// `cpp::__Str` on the Rust side.
+struct str {
+    const char *ptr;
+    size_t len;
+    const char *data() const { return ptr; }
+    size_t size() const { return len; }
+    const char *begin() const { return ptr; }
+    const char *end() const { return ptr + len; }
+    operator std::string() const { return std::string(ptr, len); }
+#ifdef RUST_CPP_HAS_STRING_VIEW
+    operator std::string_view() const { return std::string_view(ptr, len); }
+#endif
+};
+
+// The C++ side of `cpp::CppString`. With libstdc++, a std::string is not relocatable (a short
+// string points into the object itself), so it is allocated on the heap.
+class cpp_string {
+    std::unique_ptr<std::string> m_string;
+public:
+    cpp_string() : m_string(new std::string) {}
+    cpp_string(std::string s) : m_string(new std::string(std::move(s))) {}
+    cpp_string(const cpp_string &other) : m_string(new std::string(*other.m_string)) {}
+    cpp_string &operator=(const cpp_string &other) { *m_string = *other.m_string; return *this; }
+    std::string &get() { return *m_string; }
+    const std::string &get() const { return *m_string; }
+    operator std::string &() { return *m_string; }
+    operator const std::string &() const { return *m_string; }
+    std::string *operator->() { return m_string.get(); }
+    const std::string *operator->() const { return m_string.get(); }
+    friend bool operator==(const cpp_string &a, const cpp_string &b) { return a.get() == b.get(); }
+    friend bool operator<(const cpp_string &a, const cpp_string &b) { return a.get() < b.get(); }
+};
+
+// The C++ side of `cpp::CppVector<T>`, a std::vector allocated on the heap like cpp_string
+template<typename T> class vector {
+    std::unique_ptr<std::vector<T>> m_vector;
+public:
+    vector() : m_vector(new std::vector<T>) {}
+    vector(std::vector<T> v) : m_vector(new std::vector<T>(std::move(v))) {}
+    vector(const vector &other) : m_vector(new std::vector<T>(*other.m_vector)) {}
+    vector &operator=(const vector &other) { *m_vector = *other.m_vector; return *this; }
+    std::vector<T> &get() { return *m_vector; }
+    const std::vector<T> &get() const { return *m_vector; }
+    operator std::vector<T> &() { return *m_vector; }
+    operator const std::vector<T> &() const { return *m_vector; }
+    std::vector<T> *operator->() { return m_vector.get(); }
+    const std::vector<T> *operator->() const { return m_vector.get(); }
+};
+
+// Whether T can be moved in memory with memcpy, as required by the cpp_class! which are neither
+// pinned nor boxed. It can be specialized for the relocatable classes which are not detected.
+template<typename T> struct is_relocatable : std::integral_constant<bool,
+#ifdef __has_builtin
+#if __has_builtin(__builtin_is_cpp_trivially_relocatable)
+    __builtin_is_cpp_trivially_relocatable(T) ||
+#elif __has_builtin(__is_trivially_relocatable)
+    __is_trivially_relocatable(T) ||
+#endif
+#endif
+#if !defined(__GNUC__) || (__GNUC__ + 0 >= 5) || defined(__clang__)
+    std::is_trivially_copyable<T>::value ||
+#endif
+    false> {};
+template<typename T> struct is_relocatable<std::unique_ptr<T>> : std::true_type {};
+template<typename T> struct is_relocatable<std::shared_ptr<T>> : std::true_type {};
+template<> struct is_relocatable<cpp_string> : std::true_type {};
+template<typename T> struct is_relocatable<vector<T>> : std::true_type {};
+
+// The functions used by `cpp::CppVector<T>`, see `cpp::VectorHelpers`
+struct vector_helpers {
+    void (*create)(void *v);
+    void (*destroy)(void *v);
+    void (*copy)(const void *src, void *dest);
+    size_t (*len)(const void *v);
+    void *(*data)(void *v);
+    void (*push)(void *v, void *value);
+    void (*reserve)(void *v, size_t additional);
+};
+
+template<typename T> struct vector_helpers_impl {
+    static void create(void *v) { new (v) vector<T>(); }
+    static void destroy(void *v) { static_cast<vector<T>*>(v)->~vector<T>(); }
+    template<typename U = T>
+    static typename std::enable_if<std::is_copy_constructible<U>::value>::type
+    copy(const void *src, void *dest) { new (dest) vector<T>(*static_cast<const vector<T>*>(src)); }
+    template<typename U = T>
+    static typename std::enable_if<!std::is_copy_constructible<U>::value>::type
+    copy(const void *, void *) { std::abort(); }
+    static size_t len(const void *v) { return static_cast<const vector<T>*>(v)->get().size(); }
+    static void *data(void *v) { return static_cast<vector<T>*>(v)->get().data(); }
+    // Move the value, which is then destroyed, to the end of the vector
+    template<typename U = T>
+    static typename std::enable_if<std::is_move_constructible<U>::value>::type
+    push(void *v, void *value) {
+        T *t = static_cast<T*>(value);
+        static_cast<vector<T>*>(v)->get().push_back(std::move(*t));
+        t->~T();
+    }
+    template<typename U = T>
+    static typename std::enable_if<!std::is_move_constructible<U>::value>::type
+    push(void *, void *) { std::abort(); }
+    static void reserve(void *v, size_t additional) {
+        std::vector<T> &vec = static_cast<vector<T>*>(v)->get();
+        vec.reserve(vec.size() + additional);
+    }
+    static const vector_helpers *get() {
+        static const vector_helpers helpers = {
+            &create, &destroy, &copy<T>, &len, &data, &push<T>, &reserve
+        };
+        return &helpers;
+    }
+};
+
+// Write the text of an object to the Rust `fmt::Formatter`, for `#[derive(Debug)]`. Types
+// without an `operator<<` are printed as their type name and address.
+typedef void (*debug_callback)(void *formatter, const char *data, size_t len);
+template<typename T, typename = void> struct has_ostream_operator : std::false_type {};
+template<typename T> struct has_ostream_operator<T,
+    decltype(void(std::declval<std::ostream&>() << std::declval<const T&>()))> : std::true_type {};
+template<typename T>
+typename std::enable_if<has_ostream_operator<T>::value>::type
+debug_helper(const T &obj, const char *, void *formatter, debug_callback callback) {
+    std::ostringstream stream;
+    stream << obj;
+    std::string text = stream.str();
+    callback(formatter, text.data(), text.size());
+}
+template<typename T>
+typename std::enable_if<!has_ostream_operator<T>::value>::type
+debug_helper(const T &obj, const char *name, void *formatter, debug_callback callback) {
+    std::ostringstream stream;
+    stream << name << " @ " << static_cast<const void*>(&obj);
+    std::string text = stream.str();
+    callback(formatter, text.data(), text.size());
+}
+
+// Forward an exception caught by a `cpp!(try ...)` closure to Rust
+typedef void (*exception_callback)(void *exception, const char *what, const char *type);
+inline void forward_exception(void *exception, exception_callback callback, const char *what,
+                              const char *type) {
+#ifdef __GNUC__
+    int status = -1;
+    char *demangled = abi::__cxa_demangle(type, nullptr, nullptr, &status);
+    callback(exception, what, status == 0 ? demangled : type);
+    std::free(demangled);
+#else
+    callback(exception, what, type);
+#endif
+}
+
+// Filled by a `rust!` callback when its Rust code panicked. `set` must stay the first member, it
+// is called from Rust.
+struct rust_panic_info {
+    void (*set)(rust_panic_info *info, const char *message, size_t len);
+    bool panicked;
+    std::string message;
+    rust_panic_info() : set(&set_impl), panicked(false) {}
+    static void set_impl(rust_panic_info *info, const char *message, size_t len) {
+        info->panicked = true;
+        info->message.assign(message, len);
+    }
+};
+
+// The exception thrown when a `rust!` callback panicked, with the `Rethrow` panic policy
+class rust_panic : public std::exception {
+    std::string m_message;
+public:
+    explicit rust_panic(std::string message) : m_message(std::move(message)) {}
+    const char *what() const noexcept override { return m_message.c_str(); }
+};
+
+inline void handle_rust_panic(const rust_panic_info &info) {
+    if (!info.panicked)
+        return;
+#ifdef RUST_CPP_RETHROW_PANICS
+    throw rust_panic(info.message);
+#else
+    std::fprintf(stderr, "rust-cpp: aborting because a rust! callback panicked: %s\n",
+                 info.message.c_str());
+    std::abort();
+#endif
+}
+
+// Call a `rust!` callback, and handle the panic it reported, if any
+template<typename R, typename... Params, typename... Args>
+R call_rust(R (*f)(rust_panic_info *, Params...), Args &&... args) {
+    rust_panic_info info;
+    R result = f(&info, std::forward<Args>(args)...);
+    handle_rust_panic(info);
+    return result;
+}
+template<typename... Params, typename... Args>
+void call_rust_void(void (*f)(rust_panic_info *, Params...), Args &&... args) {
+    rust_panic_info info;
+    f(&info, std::forward<Args>(args)...);
+    handle_rust_panic(info);
+}
 
 template<typename T> int compare_helper(const T &a, const T&b, int cmp) {
     switch (cmp) {
@@ -124,11 +418,33 @@ template<typename T> int compare_helper(const T &a, const T&b, int cmp) {
 }
 }
 
+#define RUST_CPP_CATCH(EXCEPTION, CALLBACK) \
+    catch (const std::exception &e) { \
+        rustcpp::forward_exception(EXCEPTION, CALLBACK, e.what(), typeid(e).name()); \
+    } catch (...) { \
+        rustcpp::forward_exception(EXCEPTION, CALLBACK, "unknown exception", ""); \
+    }
+
 #define RUST_CPP_CLASS_HELPER(HASH, ...) \
     extern "C" { \
     void __cpp_destructor_##HASH(void *ptr) { typedef __VA_ARGS__ T; static_cast<T*>(ptr)->~T(); } \
     void __cpp_copy_##HASH(const void *src, void *dest) { rustcpp::copy_helper<__VA_ARGS__>(src, dest); } \
     void __cpp_default_##HASH(void *dest) { rustcpp::default_helper<__VA_ARGS__>(dest); } \
+    void __cpp_move_##HASH(void *src, void *dest) { rustcpp::move_helper<__VA_ARGS__>(src, dest); } \
+    void __cpp_copy_assign_##HASH(const void *src, void *dest) { rustcpp::copy_assign_helper<__VA_ARGS__>(src, dest); } \
+    void __cpp_move_assign_##HASH(void *src, void *dest) { rustcpp::move_assign_helper<__VA_ARGS__>(src, dest); } \
+    }
+
+#define RUST_CPP_BOXED_HELPER(HASH, ...) \
+    extern "C" { \
+    void __cpp_destructor_##HASH(void *ptr) { typedef rustcpp::boxed<__VA_ARGS__> T; static_cast<T*>(ptr)->~T(); } \
+    void __cpp_copy_##HASH(const void *src, void *dest) { rustcpp::boxed_copy_helper<__VA_ARGS__>(src, dest); } \
+    void __cpp_default_##HASH(void *dest) { rustcpp::boxed_default_helper<__VA_ARGS__>(dest); } \
+    }
+
+#define RUST_CPP_VECTOR_HELPER(NAME, ...) \
+    extern "C" const rustcpp::vector_helpers *__cpp_vector_helpers_##NAME() { \
+        return rustcpp::vector_helpers_impl<__VA_ARGS__>::get(); \
     }
 "#;
 
@@ -143,13 +459,71 @@ NOTE: rust-cpp's build function must be run in a build script."#
     ));
 }
 
-fn gen_cpp_lib(visitor: &parser::Parser) -> PathBuf {
-    let result_path = CPP_DIR.join("cpp_closures.cpp");
-    let mut output = File::create(&result_path).expect("Unable to generate temporary C++ file");
+/// Generate the C++ translation units: a single one for the whole crate, or one
+/// per module if `per_module` is true. Returns the paths of the generated files.
+fn gen_cpp_lib(visitor: &parser::Parser, per_module: bool, dir: &Path) -> Vec<PathBuf> {
+    let units: Vec<(String, Vec<&parser::Module>)> = if per_module && !visitor.modules.is_empty() {
+        visitor
+            .modules
+            .iter()
+            .map(|m| (unit_name(&m.path), vec![m]))
+            .collect()
+    } else {
+        vec![("cpp_closures".to_owned(), visitor.modules.iter().collect())]
+    };
+
+    // The closures which were already generated, in case the same closure is in several modules
+    let mut hashmap = HashMap::new();
+    units
+        .iter()
+        .enumerate()
+        .map(|(i, (name, modules))| {
+            let mut output = Vec::new();
+            gen_translation_unit(&mut output, visitor, modules, i == 0, &mut hashmap);
+            let path = dir.join(format!("{}.cpp", name));
+            // Only write the file if it changed, to keep its modification time
+            if fs::read(&path).map_or(true, |old| old != output) {
+                fs::write(&path, output).expect("Unable to generate temporary C++ file");
+            }
+            path
+        })
+        .collect()
+}
+
+/// The C++ type through which a capture is passed from Rust. A Rust `&str`
+/// captured as a `std::string_view` is passed as a `rustcpp::str`, to which
+/// the Rust code converts it, and converted at the beginning of the closure.
+fn passed_type(cpp: &str) -> &str {
+    match cpp {
+        "std::string_view" => "rustcpp::str",
+        _ => cpp,
+    }
+}
 
+/// The name of the translation unit of a module: its path relative to the crate
+fn unit_name(path: &Path) -> String {
+    // Not `CARGO_MANIFEST_DIR`, which is only set in a build script
+    let manifest_dir = env::var_os("CARGO_MANIFEST_DIR").map(PathBuf::from);
+    path.strip_prefix(manifest_dir.unwrap_or_default())
+        .unwrap_or(path)
+        .to_string_lossy()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Generate the code of the closures, classes and snippets of `modules`. The
+/// array of callbacks for the `rust!` macros is defined in the first unit.
+fn gen_translation_unit<'a>(
+    output: &mut Vec<u8>,
+    visitor: &parser::Parser,
+    modules: &[&'a parser::Module],
+    first_unit: bool,
+    hashmap: &mut HashMap<u64, &'a ClosureSig>,
+) {
     write!(output, "{}", INTERNAL_CPP_STRUCTS).unwrap();
 
-    if visitor.callbacks_count > 0 {
+    if visitor.callbacks_count > 0 && first_unit {
         #[rustfmt::skip]
         write_add_line!(output, r#"
 extern "C" {{
@@ -159,25 +533,30 @@ extern "C" {{
             file_hash = *FILE_HASH,
             callbacks_count = visitor.callbacks_count
         ).unwrap();
+    } else if visitor.callbacks_count > 0 {
+        #[rustfmt::skip]
+        write_add_line!(output, r#"
+extern "C" {{
+    extern void (*rust_cpp_callbacks{file_hash}[])();
+}}
+        "#,
+            file_hash = *FILE_HASH
+        ).unwrap();
     }
 
-    write!(output, "{}\n\n", &visitor.snippets).unwrap();
-
-    let mut hashmap = HashMap::new();
+    for module in modules {
+        write!(output, "{}\n\n", &module.snippets).unwrap();
+    }
 
     let mut sizealign = vec![];
-    for &Closure {
-        ref body_str,
-        ref sig,
-        ref callback_offset,
+    for Closure {
+        body_str,
+        sig,
+        callback_offset,
         ..
-    } in &visitor.closures
+    } in modules.iter().flat_map(|m| &m.closures)
     {
-        let &ClosureSig {
-            ref captures,
-            ref cpp,
-            ..
-        } = sig;
+        let ClosureSig { captures, cpp, .. } = sig;
 
         let hash = sig.name_hash();
         let name = sig.extern_name();
@@ -213,61 +592,105 @@ extern "C" {{
                 rustcpp::Flags<{type}>::value | {callback_offset}ull << 32
             }}", hash=hash, type=cpp, callback_offset = callback_offset));
         }
-        for &Capture { ref cpp, .. } in captures {
+        for Capture { cpp, .. } in captures {
             sizealign.push(format!("{{
                 {hash}ull,
                 sizeof({type}),
                 rustcpp::AlignOf<{type}>::value,
                 rustcpp::Flags<{type}>::value
-            }}", hash=hash, type=cpp));
+            }}", hash=hash, type=passed_type(cpp)));
         }
 
         // Generate the parameters and function declaration
-        let params = captures
-            .iter()
-            .map(
-                |&Capture {
-                     mutable,
-                     ref name,
-                     ref cpp,
-                 }| {
-                    if mutable {
-                        format!("{} & {}", cpp, name)
-                    } else {
-                        format!("{} const& {}", cpp, name)
-                    }
-                },
-            )
-            .collect::<Vec<_>>()
-            .join(", ");
-
-        if is_void {
+        let mut params = Vec::new();
+        let mut args = Vec::new();
+        let mut prelude = String::new();
+        for &Capture {
+            mutable,
+            ref name,
+            ref cpp,
+        } in captures
+        {
+            let param = if passed_type(cpp) != cpp {
+                // Converted at the beginning of the body
+                let param = format!("{}__rustcpp", name);
+                prelude.push_str(&format!(" {} {}({});", cpp, name, param));
+                params.push(format!("{} const& {}", passed_type(cpp), param));
+                param
+            } else {
+                if mutable {
+                    params.push(format!("{} & {}", cpp, name));
+                } else {
+                    params.push(format!("{} const& {}", cpp, name));
+                }
+                name.to_string()
+            };
+            args.push(param);
+        }
+        let params = params.join(", ");
+        let args = args.join(", ");
+        let comma = if params.is_empty() { "" } else { "," };
+        if sig.exception && is_void {
+            #[rustfmt::skip]
+            write_add_line!(output, r#"
+extern "C" {{
+void {name}({params}{comma} void* __exception, rustcpp::exception_callback __set_exception) {{
+    try {{{prelude}
+{body}
+    }} RUST_CPP_CATCH(__exception, __set_exception)
+}}
+}}
+"#,
+                name = &name,
+                params = params,
+                comma = comma,
+                prelude = prelude,
+                body = body_str
+            ).unwrap();
+        } else if sig.exception {
+            #[rustfmt::skip]
+            write_add_line!(output, r#"
+static inline {ty} {name}_impl({params}) {{{prelude}
+{body}
+}}
+extern "C" {{
+void {name}({params}{comma} void* __result, void* __exception, rustcpp::exception_callback __set_exception) {{
+    try {{
+        ::new(__result) ({ty})({name}_impl({args}));
+    }} RUST_CPP_CATCH(__exception, __set_exception)
+}}
+}}
+"#,
+                name = &name,
+                params = params,
+                comma = comma,
+                ty = cpp,
+                args = args,
+                prelude = prelude,
+                body = body_str
+            ).unwrap();
+        } else if is_void {
             #[rustfmt::skip]
             write_add_line!(output, r#"
 extern "C" {{
-void {name}({params}) {{
+void {name}({params}) noexcept {{{prelude}
 {body}
 }}
 }}
 "#,
                 name = &name,
                 params = params,
+                prelude = prelude,
                 body = body_str
             ).unwrap();
         } else {
-            let comma = if params.is_empty() { "" } else { "," };
-            let args = captures
-                .iter()
-                .map(|&Capture { ref name, .. }| name.to_string())
-                .collect::<Vec<_>>()
-                .join(", ");
             #[rustfmt::skip]
             write_add_line!(output, r#"
-static inline {ty} {name}_impl({params}) {{
+static inline {ty} {name}_impl({params}) {{{prelude}
 {body}
 }}
 extern "C" {{
-void {name}({params}{comma} void* __result) {{
+void {name}({params}{comma} void* __result) noexcept {{
     ::new(__result) ({ty})({name}_impl({args}));
 }}
 }}
@@ -277,13 +700,21 @@ void {name}({params}{comma} void* __result) {{
                 comma = comma,
                 ty = cpp,
                 args = args,
+                prelude = prelude,
                 body = body_str
             ).unwrap();
         }
     }
 
-    for class in &visitor.classes {
+    for class in modules.iter().flat_map(|m| &m.classes) {
         let hash = class.name_hash();
+        // The C++ type of the Rust struct, and how to get a reference to the C++
+        // class from a pointer to it
+        let (ty, d) = if class.boxed {
+            (format!("rustcpp::boxed<{}>", class.cpp), "**")
+        } else {
+            (class.cpp.clone(), "*")
+        };
 
         // Generate the sizes array
         sizealign.push(format!("{{
@@ -291,29 +722,193 @@ void {name}({params}{comma} void* __result) {{
                 sizeof({type}),
                 rustcpp::AlignOf<{type}>::value,
                 rustcpp::Flags<{type}>::value
-            }}", hash=hash, type=class.cpp));
+            }}", hash=hash, type=ty));
 
         // Generate helper function.
         // (this is done in a macro, which right after a #line directing pointing to the location of
         // the cpp_class! macro in order to give right line information in the possible errors)
         write!(
             output,
-            "{line}RUST_CPP_CLASS_HELPER({hash}, {cpp_name})\n",
+            "{line}RUST_CPP_{kind}_HELPER({hash}, {cpp_name})\n",
             line = class.line,
+            kind = if class.boxed { "BOXED" } else { "CLASS" },
             hash = hash,
             cpp_name = class.cpp
         )
         .unwrap();
 
+        // The vector helpers would not compile for a class which cannot be moved, so they
+        // are only generated on request
+        if class.derives("VectorElement") && !class.pinned && !class.boxed {
+            write!(
+                output,
+                "{line}RUST_CPP_VECTOR_HELPER({hash}, {cpp_name})\n",
+                line = class.line,
+                hash = hash,
+                cpp_name = class.cpp
+            )
+            .unwrap();
+        }
+
         if class.derives("PartialEq") {
             write!(output,
-                "{line}extern \"C\" bool __cpp_equal_{hash}(const {name} *a, const {name} *b) {{ return *a == *b; }}\n",
-                line = class.line, hash = hash, name = class.cpp).unwrap();
+                "{line}extern \"C\" bool __cpp_equal_{hash}(const {ty} *a, const {ty} *b) {{ return {d}a == {d}b; }}\n",
+                line = class.line, hash = hash, ty = ty, d = d).unwrap();
         }
         if class.derives("PartialOrd") {
             write!(output,
-                "{line}extern \"C\" bool __cpp_compare_{hash}(const {name} *a, const {name} *b, int cmp) {{ return rustcpp::compare_helper(*a, *b, cmp); }}\n",
-                line = class.line, hash = hash, name = class.cpp).unwrap();
+                "{line}extern \"C\" bool __cpp_compare_{hash}(const {ty} *a, const {ty} *b, int cmp) {{ return rustcpp::compare_helper({d}a, {d}b, cmp); }}\n",
+                line = class.line, hash = hash, ty = ty, d = d).unwrap();
+        }
+        for (derive, method, op) in BINARY_OPERATORS.iter() {
+            if class.derives(derive) {
+                write!(output,
+                    "{line}extern \"C\" void __cpp_{method}_{hash}(const {ty} *a, const {ty} *b, {ty} *r) {{ new (r) {ty}({d}a {op} {d}b); }}\n",
+                    line = class.line, method = method, hash = hash, ty = ty, d = d, op = op).unwrap();
+            }
+        }
+        for (derive, method, op) in ASSIGN_OPERATORS.iter() {
+            if class.derives(derive) {
+                write!(output,
+                    "{line}extern \"C\" void __cpp_{method}_{hash}({ty} *a, const {ty} *b) {{ {d}a {op} {d}b; }}\n",
+                    line = class.line, method = method, hash = hash, ty = ty, d = d, op = op).unwrap();
+            }
+        }
+        for (derive, method, op) in UNARY_OPERATORS.iter() {
+            if class.derives(derive) {
+                write!(output,
+                    "{line}extern \"C\" void __cpp_{method}_{hash}(const {ty} *a, {ty} *r) {{ new (r) {ty}({op}{d}a); }}\n",
+                    line = class.line, method = method, hash = hash, ty = ty, d = d, op = op).unwrap();
+            }
+        }
+        for operator in class.operators().unwrap_or_default() {
+            let op_hash = class.operator_hash(&operator);
+            let type_entry = |cpp: &str| {
+                format!("{{
+                {hash}ull,
+                sizeof({type}),
+                rustcpp::AlignOf<{type}>::value,
+                rustcpp::Flags<{type}>::value
+            }}", hash=op_hash, type=cpp)
+            };
+            match operator {
+                ClassOperator::Index {
+                    mutable,
+                    index_cpp,
+                    output_cpp,
+                    ..
+                } => {
+                    // The first entry is the output, followed by the index
+                    sizealign.push(type_entry(&output_cpp));
+                    sizealign.push(type_entry(&index_cpp));
+                    write!(output,
+                        "{line}extern \"C\" const {out} *__cpp_index_{hash}(const {ty} *a, const {index} *i) {{ return std::addressof(({d}a)[*i]); }}\n",
+                        line = class.line, hash = op_hash, ty = ty, d = d, index = index_cpp, out = output_cpp).unwrap();
+                    if mutable {
+                        write!(output,
+                            "{line}extern \"C\" {out} *__cpp_index_mut_{hash}({ty} *a, const {index} *i) {{ return std::addressof(({d}a)[*i]); }}\n",
+                            line = class.line, hash = op_hash, ty = ty, d = d, index = index_cpp, out = output_cpp).unwrap();
+                    }
+                }
+                ClassOperator::Deref {
+                    mutable,
+                    target_cpp,
+                    ..
+                } => {
+                    sizealign.push(type_entry(&target_cpp));
+                    write!(output,
+                        "{line}extern \"C\" const {target} *__cpp_deref_{hash}(const {ty} *a) {{ return std::addressof(*{d}a); }}\n",
+                        line = class.line, hash = op_hash, ty = ty, d = d, target = target_cpp).unwrap();
+                    if mutable {
+                        write!(output,
+                            "{line}extern \"C\" {target} *__cpp_deref_mut_{hash}({ty} *a) {{ return std::addressof(*{d}a); }}\n",
+                            line = class.line, hash = op_hash, ty = ty, d = d, target = target_cpp).unwrap();
+                    }
+                }
+            }
+        }
+        if class.derives("Hash") {
+            write!(output,
+                "{line}extern \"C\" size_t __cpp_hash_{hash}(const {ty} *a) {{ return std::hash<{name}>()({d}a); }}\n",
+                line = class.line, hash = hash, ty = ty, d = d, name = class.cpp).unwrap();
+        }
+        if class.derives("Debug") {
+            write!(output,
+                "{line}extern \"C\" void __cpp_debug_{hash}(const {ty} *a, void *f, rustcpp::debug_callback cb) {{ rustcpp::debug_helper({d}a, {name:?}, f, cb); }}\n",
+                line = class.line, hash = hash, ty = ty, d = d, name = class.cpp).unwrap();
+        }
+        for member in &class.members {
+            let member_hash = class.member_hash(member);
+            let type_entry = |cpp: &str| {
+                format!("{{
+                {hash}ull,
+                sizeof({type}),
+                rustcpp::AlignOf<{type}>::value,
+                rustcpp::Flags<{type}>::value
+            }}", hash=member_hash, type=cpp)
+            };
+
+            // The first entry is the return value, followed by the arguments
+            if member.constructor {
+                let arg_types = member.args.iter().map(|a| format!(", const {}&", a.cpp));
+                sizealign.push(format!("{{
+                {hash}ull,
+                sizeof({type}),
+                rustcpp::AlignOf<{type}>::value,
+                rustcpp::Flags<{type}>::value |
+                    (std::is_constructible<{cpp}{args}>::value << {flag})
+            }}", hash=member_hash, type=ty, cpp=class.cpp, args=arg_types.collect::<String>(),
+                    flag=flags::IS_CONSTRUCTIBLE));
+            } else {
+                match member.ret {
+                    Some((_, ref cpp)) => sizealign.push(type_entry(cpp)),
+                    None => sizealign.push(format!("{{{}ull, 0, 1, 0}}", member_hash)),
+                }
+            }
+            let mut params = Vec::new();
+            let mut args = Vec::new();
+            match member.receiver {
+                Receiver::None => {}
+                Receiver::Ref => params.push(format!("const {} *self", ty)),
+                Receiver::Mut => params.push(format!("{} *self", ty)),
+            }
+            for arg in &member.args {
+                sizealign.push(type_entry(&arg.cpp));
+                params.push(format!("const {} *{}", arg.cpp, arg.name));
+                args.push(format!("*{}", arg.name));
+            }
+            let call = match member.receiver {
+                Receiver::None => format!("{}::{}({})", class.cpp, member.name, args.join(", ")),
+                _ => format!("({}self).{}({})", d, member.name, args.join(", ")),
+            };
+            let body = match member.ret {
+                _ if member.constructor => {
+                    params.push("void *result".to_owned());
+                    let mut template_args = vec![class.cpp.clone()];
+                    template_args.extend(member.args.iter().map(|a| a.cpp.clone()));
+                    args.insert(0, "result".to_owned());
+                    format!(
+                        "rustcpp::{}construct_helper<{}>({});",
+                        if class.boxed { "boxed_" } else { "" },
+                        template_args.join(", "),
+                        args.join(", ")
+                    )
+                }
+                Some((_, ref cpp)) => {
+                    params.push("void *result".to_owned());
+                    format!("::new (result) ({})({});", cpp, call)
+                }
+                None => format!("{};", call),
+            };
+            write!(
+                output,
+                "{line}extern \"C\" void {name}({params}) {{ {body} }}\n",
+                line = class.line,
+                name = class.member_extern_name(member),
+                params = params.join(", "),
+                body = body
+            )
+            .unwrap();
         }
     }
 
@@ -341,6 +936,10 @@ struct Flags {{
     static const uintptr_t value =
         (std::is_copy_constructible<T>::value << {flag_is_copy_constructible}) |
         (std::is_default_constructible<T>::value << {flag_is_default_constructible}) |
+        (std::is_move_constructible<T>::value << {flag_is_move_constructible}) |
+        (std::is_copy_assignable<T>::value << {flag_is_copy_assignable}) |
+        (std::is_move_assignable<T>::value << {flag_is_move_assignable}) |
+        (rustcpp::is_relocatable<T>::value << {flag_is_trivially_relocatable}) |
 #if !defined(__GNUC__) || (__GNUC__ + 0 >= 5) || defined(__clang__)
         (std::is_trivially_destructible<T>::value << {flag_is_trivially_destructible}) |
         (std::is_trivially_copyable<T>::value << {flag_is_trivially_copyable}) |
@@ -349,6 +948,17 @@ struct Flags {{
         0;
 }};
 
+// The traits of a boxed class are the ones of the pointer, except for the constructors
+template<typename T>
+struct Flags<boxed<T>> {{
+    static const uintptr_t value =
+        (std::is_copy_constructible<T>::value << {flag_is_copy_constructible}) |
+        (std::is_default_constructible<T>::value << {flag_is_default_constructible}) |
+        (std::is_move_constructible<boxed<T>>::value << {flag_is_move_constructible}) |
+        (std::is_move_assignable<boxed<T>>::value << {flag_is_move_assignable}) |
+        (1 << {flag_is_trivially_relocatable});
+}};
+
 struct SizeAlign {{
     uint64_t hash;
     uint64_t size;
@@ -363,11 +973,19 @@ struct MetaData {{
     SizeAlign data[{length}];
 }};
 
+#ifdef _MSC_VER
+#pragma section("{section}", read)
+#endif
+
 MetaData
-#ifdef _WIN32
-    __declspec (selectany)
+#if defined(_MSC_VER)
+    __declspec(allocate("{section}")) __declspec(selectany)
+#elif defined(_WIN32)
+    __attribute__((section("{section}"), used)) __declspec(selectany)
+#elif defined(__APPLE__)
+    __attribute__((section("__DATA,{macho_section}"), used, weak))
 #elif __GNUC__
-    __attribute__((weak))
+    __attribute__((section("{section}"), used, weak))
 #endif
     metadata = {{
     {{ {magic} }},
@@ -382,32 +1000,68 @@ MetaData
         length = sizealign.len(),
         magic = magic.join(", "),
         version = VERSION,
+        section = METADATA_SECTION,
+        macho_section = MACHO_METADATA_SECTION,
         flag_is_copy_constructible = flags::IS_COPY_CONSTRUCTIBLE,
         flag_is_default_constructible = flags::IS_DEFAULT_CONSTRUCTIBLE,
+        flag_is_move_constructible = flags::IS_MOVE_CONSTRUCTIBLE,
+        flag_is_copy_assignable = flags::IS_COPY_ASSIGNABLE,
+        flag_is_move_assignable = flags::IS_MOVE_ASSIGNABLE,
+        flag_is_trivially_relocatable = flags::IS_TRIVIALLY_RELOCATABLE,
         flag_is_trivially_destructible = flags::IS_TRIVIALLY_DESTRUCTIBLE,
         flag_is_trivially_copyable = flags::IS_TRIVIALLY_COPYABLE,
         flag_is_trivially_default_constructible = flags::IS_TRIVIALLY_DEFAULT_CONSTRUCTIBLE,
     ).unwrap();
+}
 
-    result_path
+/// What happens when the Rust code of a `rust!` callback panics, see
+/// [`Config::panic_policy`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// Print a message and abort the process.
+    Abort,
+    /// Throw a `rustcpp::rust_panic` C++ exception, whose `what()` is the
+    /// panic message.
+    Rethrow,
 }
 
-fn clean_artifacts() {
-    if CPP_DIR.is_dir() {
-        remove_dir_all(&*CPP_DIR).expect(
-            r#"
--- rust-cpp fatal error --
+/// An error of [`Config::generate_only`] and [`Config::write_compile_commands`].
+#[derive(Debug)]
+pub enum GenerateError {
+    /// The crate could not be parsed
+    Parse(Error),
+    /// The generated files could not be written
+    Io(std::io::Error),
+}
 
-Failed to remove existing build artifacts from output directory."#,
-        );
+impl From<Error> for GenerateError {
+    fn from(e: Error) -> Self {
+        GenerateError::Parse(e)
     }
+}
 
-    create_dir(&*CPP_DIR).expect(
-        r#"
--- rust-cpp fatal error --
+impl From<std::io::Error> for GenerateError {
+    fn from(e: std::io::Error) -> Self {
+        GenerateError::Io(e)
+    }
+}
 
-Failed to create output object directory."#,
-    );
+impl std::fmt::Display for GenerateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            GenerateError::Parse(e) => write!(f, "{}", e),
+            GenerateError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for GenerateError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GenerateError::Parse(_) => None,
+            GenerateError::Io(e) => Some(e),
+        }
+    }
 }
 
 /// This struct is for advanced users of the build script. It allows providing
@@ -420,7 +1074,14 @@ Failed to create output object directory."#,
 /// `cc::Build` object.
 pub struct Config {
     cc: cc::Build,
-    std_flag_set: bool, // true if the -std flag was specified
+    std_flag_set: bool,  // true if the -std flag was specified
+    files: Vec<PathBuf>, // Files added with `file`, compiled after the generated code
+    per_module: bool,
+    cargo_metadata: bool,
+    panic_policy: PanicPolicy,
+    fail_on_parse_error: bool,
+    compile_commands_dir: Option<PathBuf>,
+    mirror_modules: bool,
 }
 
 impl Default for Config {
@@ -439,6 +1100,13 @@ impl Config {
         Config {
             cc,
             std_flag_set: false,
+            files: Vec::new(),
+            per_module: false,
+            cargo_metadata: true,
+            panic_policy: PanicPolicy::Abort,
+            fail_on_parse_error: false,
+            compile_commands_dir: None,
+            mirror_modules: false,
         }
     }
 
@@ -482,7 +1150,80 @@ impl Config {
     // XXX: Make sure this works with sizes logic
     /// Add a file which will be compiled
     pub fn file<P: AsRef<Path>>(&mut self, p: P) -> &mut Self {
-        self.cc.file(p);
+        self.files.push(p.as_ref().to_owned());
+        self
+    }
+
+    /// Generate one C++ translation unit per Rust source file, instead of a
+    /// single one for the whole crate. Defaults to `false`.
+    ///
+    /// Only the translation units of the modules whose C++ code changed are
+    /// compiled again, which makes incremental builds faster for crates with
+    /// a lot of C++ code.
+    ///
+    /// Note that the raw C++ code of a `cpp!{{ ... }}` block is then only
+    /// included in the translation unit of the file containing it, so it is
+    /// no longer visible from the `cpp!` macros of the other files. The
+    /// declarations shared by several files should be in a header included by
+    /// each of them.
+    ///
+    /// This is not the default because it breaks the crates relying on that
+    /// visibility, which is the case of any crate declaring a class in a
+    /// `cpp!{{ ... }}` block of its root and using it from another module.
+    /// Including all the blocks in each unit instead would define their
+    /// functions and variables several times.
+    pub fn translation_unit_per_module(&mut self, per_module: bool) -> &mut Self {
+        self.per_module = per_module;
+        self
+    }
+
+    /// Set what happens when the Rust code of a `rust!` callback panics.
+    /// Defaults to `PanicPolicy::Abort`.
+    ///
+    /// A panic cannot unwind through the C++ frames, so it is caught at the
+    /// end of the callback. With `PanicPolicy::Rethrow`, it is then thrown
+    /// again as a `rustcpp::rust_panic` exception, which the C++ code may
+    /// catch. The C++ functions of the `cpp!` closures are `noexcept`, so an
+    /// exception escaping one of them calls `std::terminate`, unless it is a
+    /// `cpp!(try ...)` closure, which returns it as a `cpp::CppException`.
+    pub fn panic_policy(&mut self, policy: PanicPolicy) -> &mut Self {
+        self.panic_policy = policy;
+        self
+    }
+
+    /// Define whether the build script fails when the crate cannot be parsed.
+    /// Defaults to `false`.
+    ///
+    /// By default, the parse error is printed as a warning and the build script
+    /// exits successfully, such that rustc can report the error, which is
+    /// usually in the Rust code, with a better message.
+    pub fn fail_on_parse_error(&mut self, fail: bool) -> &mut Self {
+        self.fail_on_parse_error = fail;
+        self
+    }
+
+    /// Write a `compile_commands.json` to this directory when building, with
+    /// the exact command line, include paths, definitions and flags with which
+    /// the generated C++ code and the C++ files added with `file` are compiled.
+    /// This lets tools such as clangd understand the embedded C++ code.
+    ///
+    /// A relative directory is relative to the crate directory. For example,
+    /// `.compile_commands_dir(".")` writes it next to the `Cargo.toml`, where
+    /// clangd looks for it.
+    pub fn compile_commands_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.compile_commands_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Define whether a copy of the generated C++ code of each Rust module is
+    /// written to the `cpp_modules` subdirectory of the `compile_commands_dir`,
+    /// and listed in the `compile_commands.json`. Defaults to `false`.
+    ///
+    /// The `#line` directives of these files point to the Rust sources, and
+    /// each of them only contains the C++ code of one module, which makes it
+    /// easier to open the C++ code of a module in an editor.
+    pub fn mirror_modules(&mut self, mirror: bool) -> &mut Self {
+        self.mirror_modules = mirror;
         self
     }
 
@@ -609,9 +1350,10 @@ impl Config {
     }
 
     /// Define whether metadata should be emitted for cargo allowing it to
-    /// automatically link the binary. Defaults to `true`.
+    /// automatically link the binary, and to know when the build script must
+    /// be run again. Defaults to `true`.
     pub fn cargo_metadata(&mut self, cargo_metadata: bool) -> &mut Self {
-        // XXX: Use this to control the cargo metadata which rust-cpp produces
+        self.cargo_metadata = cargo_metadata;
         self.cc.cargo_metadata(cargo_metadata);
         self
     }
@@ -631,34 +1373,195 @@ impl Config {
     /// This method does not perform rust codegen - that is performed by `cpp`
     /// and `cpp_macros`, which perform the actual procedural macro expansion.
     ///
+    /// If the C++ code fails to compile, the diagnostics of the compiler are
+    /// printed again as cargo warnings, grouped by `cpp!` macro and pointing to
+    /// the Rust source.
+    ///
     /// This method may technically be called more than once for ergonomic
     /// reasons, but that usually won't do what you want. Use a different
     /// `Config` object each time you want to build a crate.
     pub fn build<P: AsRef<Path>>(&mut self, crate_root: P) {
-        assert_eq!(
-            env!("CARGO_PKG_VERSION"),
-            VERSION,
-            "Internal Error: mismatched cpp_common and cpp_build versions"
-        );
+        let (visitor, units) = match self.generate(crate_root) {
+            Some(generated) => generated,
+            None => return,
+        };
+        self.configure_compiler();
+        self.write_configured_compile_commands(&visitor, &units);
 
-        // Clean up any leftover artifacts
-        clean_artifacts();
+        // Build the C++ library
+        let result = self.compile_units(&units).and_then(|objects| {
+            if self.cargo_metadata {
+                print_rerun_directives(&visitor.files, &self.files, &objects);
+            }
+            self.cc
+                .objects(objects)
+                .files(&self.files)
+                .try_compile(LIB_NAME)
+        });
+        match result {
+            Ok(()) => write_layout_report(&visitor),
+            Err(e) => {
+                self.report_diagnostics(&visitor, &units);
+                let _ = writeln!(std::io::stderr(), "\n\nerror occurred: {}\n\n", e);
+                #[cfg(not(feature = "docs-only"))]
+                std::process::exit(1);
+            }
+        }
+    }
 
-        // Parse the crate
+    /// Extracts `cpp` declarations from the passed-in crate root, and writes
+    /// the generated C++ code to `out_dir`, without compiling it. Returns the
+    /// paths of the generated files.
+    ///
+    /// This is for building the C++ code with another build system. The files
+    /// must be compiled with the crate directory in the include path, into a
+    /// static library named `OUT_DIR/librust_cpp_generated.a`
+    /// (`rust_cpp_generated.lib` with MSVC), which is linked into the crate
+    /// and from which the `cpp!` macros read the layout of the C++ types. The
+    /// options of this `Config` which are passed to the compiler are ignored.
+    ///
+    /// Like for `extract`, the `OUT_DIR` environment variable of the build of
+    /// the crate must be set if the closures contain `rust!` macros.
+    pub fn generate_only<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        crate_root: P,
+        out_dir: Q,
+    ) -> Result<Vec<PathBuf>, GenerateError> {
         let mut visitor = parser::Parser::default();
-        if let Err(err) = visitor.parse_crate(&crate_root) {
-            warnln!(r#"-- rust-cpp parse error --
-There was an error parsing the crate for the rust-cpp build script:
-{}
-In order to provide a better error message, the build script will exit successfully, such that rustc can provide an error message."#,
-                    err
+        visitor.parse_crate(crate_root)?;
+        let dir = out_dir.as_ref();
+        create_dir_all(dir)?;
+        // The other files of the directory are left alone, only the returned
+        // ones are to be compiled
+        let units = gen_cpp_lib(&visitor, self.per_module, dir);
+        self.configure_compiler();
+        self.write_configured_compile_commands(&visitor, &units);
+        if self.cargo_metadata {
+            print_rerun_directives(&visitor.files, &[], &[]);
+        }
+        Ok(units)
+    }
+
+    /// Extracts `cpp` declarations from the passed-in crate root, and checks
+    /// the syntax of the generated C++ code and of the C++ files added with
+    /// `file`, without building them. The diagnostics of the compiler are
+    /// printed to the standard error, pointing to the Rust sources.
+    ///
+    /// Returns `false` if there are errors, or if the compiler cannot check
+    /// the syntax only (MSVC).
+    pub fn check<P: AsRef<Path>>(&mut self, crate_root: P) -> bool {
+        let (visitor, units) = match self.generate(crate_root) {
+            Some(generated) => generated,
+            None => return false,
+        };
+        self.configure_compiler();
+        let compiler = match self.cc.try_get_compiler() {
+            Ok(compiler) => compiler,
+            Err(e) => {
+                let _ = writeln!(std::io::stderr(), "error occurred: {}", e);
+                return false;
+            }
+        };
+        let files = units.iter().chain(&self.files).cloned().collect::<Vec<_>>();
+        match diagnostics::capture(&compiler, &files) {
+            Some((diagnostics, success)) => {
+                if !diagnostics.is_empty() {
+                    let report = diagnostics::report(&diagnostics, &visitor.modules);
+                    let _ = writeln!(std::io::stderr(), "{}", report.trim_end());
+                }
+                success
+            }
+            None => {
+                let _ = writeln!(
+                    std::io::stderr(),
+                    "error: unable to check the syntax of the C++ code with {}",
+                    compiler.path().display()
                 );
-            return;
+                false
+            }
+        }
+    }
+
+    /// Extracts `cpp` declarations from the passed-in crate root, generates
+    /// the C++ code, and writes `compile_commands.json` to `dir`, with the
+    /// command lines which compile the generated code and the C++ files added
+    /// with `file`, for the tools such as clangd. Returns the path of the file.
+    ///
+    /// See also `compile_commands_dir`, to write it while building.
+    pub fn write_compile_commands<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        crate_root: P,
+        dir: Q,
+    ) -> Result<PathBuf, GenerateError> {
+        let visitor = self.try_parse(crate_root)?;
+        let units = self.generate_units(&visitor);
+        self.configure_compiler();
+        Ok(self.write_compile_database(&visitor, &units, dir.as_ref())?)
+    }
+
+    /// Write `compile_commands.json` to `dir` for the generated translation
+    /// units, the C++ files, and the mirrored modules if enabled.
+    fn write_compile_database(
+        &self,
+        visitor: &parser::Parser,
+        units: &[PathBuf],
+        dir: &Path,
+    ) -> std::io::Result<PathBuf> {
+        let compiler = self
+            .cc
+            .try_get_compiler()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+        let mut files = units.iter().chain(&self.files).cloned().collect::<Vec<_>>();
+        if self.mirror_modules {
+            let mirror_dir = dir.join("cpp_modules");
+            create_dir_all(&mirror_dir)?;
+            let mirrors = gen_cpp_lib(visitor, true, &mirror_dir);
+            remove_stale_units(&mirror_dir, &mirrors);
+            files.extend(mirrors);
         }
+        let database = compile_commands::generate(
+            &compile_commands::command(&compiler),
+            &CARGO_MANIFEST_DIR,
+            &files,
+        );
+        create_dir_all(dir)?;
+        let path = dir.join("compile_commands.json");
+        // Only write the file if it changed, not to disturb the tools watching it
+        if fs::read_to_string(&path).map_or(true, |old| old != database) {
+            fs::write(&path, database)?;
+        }
+        Ok(path)
+    }
 
-        // Generate the C++ library code
-        let filename = gen_cpp_lib(&visitor);
+    /// Write the `compile_commands.json` to the directory given with
+    /// `compile_commands_dir`, if any.
+    fn write_configured_compile_commands(&self, visitor: &parser::Parser, units: &[PathBuf]) {
+        if let Some(dir) = &self.compile_commands_dir {
+            if let Err(e) = self.write_compile_database(visitor, units, dir) {
+                warnln!("Unable to write the compile_commands.json: {}", e);
+            }
+        }
+    }
 
+    /// Parse the crate and generate the C++ code. Returns the parser and the
+    /// paths of the generated translation units.
+    fn generate<P: AsRef<Path>>(&self, crate_root: P) -> Option<(parser::Parser, Vec<PathBuf>)> {
+        let visitor = self.parse(crate_root)?;
+        let units = self.generate_units(&visitor);
+        Some((visitor, units))
+    }
+
+    /// Write the C++ code of the parsed crate to `OUT_DIR/rust_cpp`, and
+    /// return the translation units.
+    fn generate_units(&self, visitor: &parser::Parser) -> Vec<PathBuf> {
+        let units = gen_cpp_lib(visitor, self.per_module, &CPP_DIR);
+        remove_stale_units(&CPP_DIR, &units);
+        units
+    }
+
+    /// Add the flags which the generated code needs to the compiler
+    /// configuration.
+    fn configure_compiler(&mut self) {
         // Ensure C++11 mode is enabled. We rely on some C++11 construct, so we
         // must enable C++11 by default.
         // MSVC, GCC >= 5, Clang >= 6 defaults to C++14, but since we want to
@@ -667,12 +1570,180 @@ In order to provide a better error message, the build script will exit successfu
         // Ideally should be done by https://github.com/alexcrichton/cc-rs/issues/191
         if !self.std_flag_set {
             self.cc.flag_if_supported("-std=c++11");
+            self.std_flag_set = true;
         }
-        // Build the C++ library
-        if let Err(e) = self.cc.file(filename).try_compile(LIB_NAME) {
-            let _ = writeln!(std::io::stderr(), "\n\nerror occurred: {}\n\n", e);
-            #[cfg(not(feature = "docs-only"))]
-            std::process::exit(1);
+        if self.panic_policy == PanicPolicy::Rethrow {
+            self.cc.define("RUST_CPP_RETHROW_PANICS", None);
+        }
+    }
+
+    /// Parse the crate, reporting the errors according to
+    /// `fail_on_parse_error`.
+    fn parse<P: AsRef<Path>>(&self, crate_root: P) -> Option<parser::Parser> {
+        match self.try_parse(crate_root) {
+            Ok(visitor) => Some(visitor),
+            Err(err) => {
+                if self.fail_on_parse_error {
+                    let _ = writeln!(std::io::stderr(), "-- rust-cpp parse error --\n{}\n", err);
+                    #[cfg(not(feature = "docs-only"))]
+                    std::process::exit(1);
+                }
+                warnln!(
+                    r#"-- rust-cpp parse error --
+There was an error parsing the crate for the rust-cpp build script:
+{}
+In order to provide a better error message, the build script will exit successfully, such that rustc can provide an error message."#,
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    /// Parse the crate, after creating the output directory.
+    fn try_parse<P: AsRef<Path>>(&self, crate_root: P) -> Result<parser::Parser, Error> {
+        assert_eq!(
+            env!("CARGO_PKG_VERSION"),
+            VERSION,
+            "Internal Error: mismatched cpp_common and cpp_build versions"
+        );
+
+        create_dir_all(CPP_DIR.join("cache")).expect(
+            r#"
+-- rust-cpp fatal error --
+
+Failed to create output object directory."#,
+        );
+
+        let mut visitor = parser::Parser::default();
+        visitor.parse_crate(&crate_root)?;
+        Ok(visitor)
+    }
+
+    /// Compile the translation units and the C++ files again to capture the
+    /// diagnostics of the compiler, and print them as warnings pointing to the
+    /// `cpp!` macros in the Rust sources.
+    fn report_diagnostics(&self, visitor: &parser::Parser, units: &[PathBuf]) {
+        let compiler = match self.cc.try_get_compiler() {
+            Ok(compiler) => compiler,
+            Err(_) => return,
+        };
+        let files = units.iter().chain(&self.files).cloned().collect::<Vec<_>>();
+        let diagnostics = match diagnostics::capture(&compiler, &files) {
+            Some((diagnostics, _)) if !diagnostics.is_empty() => diagnostics,
+            _ => return,
+        };
+        warnln!(
+            "-- rust-cpp compilation error --\n{}",
+            diagnostics::report(&diagnostics, &visitor.modules).trim_end()
+        );
+    }
+
+    /// Compile the generated translation units, reusing the objects cached by
+    /// a previous build for the ones which did not change. Returns the paths
+    /// of the objects.
+    fn compile_units(&self, units: &[PathBuf]) -> Result<Vec<PathBuf>, cc::Error> {
+        let compiler = self.cc.try_get_compiler()?;
+        let cache_dir = CPP_DIR.join("cache");
+        let objects = units
+            .iter()
+            .map(|unit| cache::object_path(&cache_dir, &compiler, unit))
+            .collect::<Vec<_>>();
+
+        let outdated = units
+            .iter()
+            .zip(&objects)
+            .filter(|(_, object)| !cache::is_up_to_date(object))
+            .collect::<Vec<_>>();
+        if !outdated.is_empty() {
+            let mut cc = self.cc.clone();
+            cc.out_dir(CPP_DIR.join("obj"))
+                .files(outdated.iter().map(|(unit, _)| unit));
+            if !compiler.is_like_msvc() {
+                // Generate a depfile next to the object, listing the included headers
+                cc.flag("-MMD");
+            }
+            let compiled = cc.try_compile_intermediates()?;
+            for (compiled, (_, object)) in compiled.iter().zip(&outdated) {
+                fs::rename(compiled, object).expect("Unable to move object file to the cache");
+                let _ = fs::rename(cache::depfile_path(compiled), cache::depfile_path(object));
+            }
+        }
+        cache::remove_unused(&cache_dir, &objects);
+        Ok(objects)
+    }
+}
+
+/// Tell cargo to run the build script again if one of the parsed Rust files, one
+/// of the C++ files or one of the local headers they include is modified, or if
+/// the compiler configuration changes.
+fn print_rerun_directives(rust_files: &[PathBuf], cpp_files: &[PathBuf], objects: &[PathBuf]) {
+    let mut files = rust_files
+        .iter()
+        .chain(cpp_files)
+        .cloned()
+        .collect::<Vec<_>>();
+    // The headers are listed in the depfiles. (Not available with MSVC)
+    for object in objects {
+        if let Ok(deps) = fs::read_to_string(cache::depfile_path(object)) {
+            files.extend(
+                cache::parse_depfile(&deps)
+                    .into_iter()
+                    .filter(|dep| !dep.starts_with(&*OUT_DIR)),
+            );
+        }
+    }
+    let mut printed = HashSet::new();
+    for file in files {
+        if printed.insert(file.clone()) {
+            println!("cargo:rerun-if-changed={}", file.display());
+        }
+    }
+
+    // The variables used by the `cc` crate to find the compiler and its flags
+    let target = env::var("TARGET").unwrap_or_default();
+    let kind = if env::var("HOST").ok() == Some(target.clone()) {
+        "HOST"
+    } else {
+        "TARGET"
+    };
+    for var in &["CXX", "CXXFLAGS"] {
+        println!("cargo:rerun-if-env-changed={}", var);
+        println!("cargo:rerun-if-env-changed={}_{}", var, target);
+        println!(
+            "cargo:rerun-if-env-changed={}_{}",
+            var,
+            target.replace('-', "_")
+        );
+        println!("cargo:rerun-if-env-changed={}_{}", kind, var);
+    }
+}
+
+/// Write the layout report of the types used by the closures and the classes
+/// to `OUT_DIR/rust_cpp/layout.json`, from the metadata of the compiled library.
+fn write_layout_report(visitor: &parser::Parser) {
+    let metadata = match metadata::open_lib_file().and_then(metadata::read_metadata) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            warnln!("Unable to read the metadata for the layout report: {}", e);
+            return;
+        }
+    };
+    let report = layout::report(&visitor.modules, &metadata);
+    if let Err(e) = fs::write(CPP_DIR.join("layout.json"), report) {
+        warnln!("Unable to write the layout report: {}", e);
+    }
+}
+
+/// Remove the generated C++ files of `dir` which are not in `units`, left by
+/// a previous build.
+fn remove_stale_units(dir: &Path, units: &[PathBuf]) {
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().map_or(false, |e| e == "cpp") && !units.contains(&path) {
+                let _ = fs::remove_file(path);
+            }
         }
     }
 }
@@ -682,3 +1753,35 @@ In order to provide a better error message, the build script will exit successfu
 pub fn build<P: AsRef<Path>>(path: P) {
     Config::new().build(path)
 }
+
+#[test]
+fn test_generate_only() {
+    // The tests are not run by a build script, so there is no OUT_DIR
+    let test_crate = test_crate::TestCrate::new("generate_only");
+    let root = test_crate.write(
+        "lib.rs",
+        "cpp!{{ #include <string> }}\nfn foo() -> i32 { unsafe { cpp!([] -> i32 as \"int\" { return 1; }) } }\n",
+    );
+
+    let out_dir = test_crate.dir.join("generated");
+    let units = Config::new()
+        .cargo_metadata(false)
+        .generate_only(&root, &out_dir)
+        .unwrap();
+    assert_eq!(units, vec![out_dir.join("cpp_closures.cpp")]);
+    let code = fs::read_to_string(&units[0]).unwrap();
+    assert!(code.contains("#include <string>"));
+    assert!(code.contains("return 1;"));
+
+    // The name of the array of the `rust!` callbacks depends on the OUT_DIR
+    test_crate.write(
+        "lib.rs",
+        "fn foo() { unsafe { cpp!([] { rust!(cb [] {}); }) } }\n",
+    );
+    assert!(matches!(
+        Config::new()
+            .cargo_metadata(false)
+            .generate_only(&root, &out_dir),
+        Err(GenerateError::Parse(_))
+    ));
+}
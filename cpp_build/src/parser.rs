@@ -1,64 +1,144 @@
+use crate::cfg;
+use crate::expand::{self, MacroRules};
 use cpp_common::{Class, Closure, Macro, RustInvocation};
 use regex::Regex;
+use std::collections::HashMap;
 use std::fmt;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Read;
-use std::mem::swap;
+use std::mem::{swap, take};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
-use syn;
 use syn::visit::Visit;
 
+/// The position of an error in a source file, with the line of the source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub src_path: String,
+    pub line: u32,   // 1-based
+    pub column: u32, // 1-based
+    pub snippet: String,
+}
+
+impl Location {
+    /// The location of the 0-based `line` and `column` of a file. When the
+    /// column is not known, it is the first non-whitespace character of the line.
+    fn new(path: &Path, line: u32, column: Option<u32>) -> Location {
+        let snippet = fs::read_to_string(path)
+            .ok()
+            .and_then(|s| s.lines().nth(line as usize).map(str::to_owned))
+            .unwrap_or_default();
+        let column = column.unwrap_or_else(|| (snippet.len() - snippet.trim_start().len()) as u32);
+        Location {
+            src_path: path.to_string_lossy().into_owned(),
+            line: line + 1,
+            column: column + 1,
+            snippet,
+        }
+    }
+}
+
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum Error {
     ParseCannotOpenFile {
         src_path: String,
     },
     ParseSyntaxError {
-        src_path: String,
+        location: Location,
         error: syn::parse::Error,
     },
     LexError {
-        src_path: String,
-        line: u32,
+        location: Location,
+    },
+    MacroError {
+        location: Location,
+        macro_name: &'static str,
+        message: String,
     },
+    ModuleNotFound {
+        location: Location,
+        name: String,
+    },
+}
+
+impl Error {
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            Error::ParseCannotOpenFile { .. } => None,
+            Error::ParseSyntaxError { location, .. }
+            | Error::LexError { location }
+            | Error::MacroError { location, .. }
+            | Error::ModuleNotFound { location, .. } => Some(location),
+        }
+    }
 }
 
 impl fmt::Display for Error {
+    /// Format the error like rustc does: the message, followed by the location
+    /// and the line of the source
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Error::ParseCannotOpenFile { ref src_path } => {
-                write!(f, "Parsing crate: cannot open file `{}`.", src_path)
+            Error::ParseCannotOpenFile { src_path } => {
+                write!(f, "error: cannot open file `{}`", src_path)?
             }
-            Error::ParseSyntaxError {
-                ref src_path,
-                ref error,
-            } => write!(f, "Parsing file : `{}`:\n{}", src_path, error),
-            Error::LexError {
-                ref src_path,
-                ref line,
-            } => write!(f, "{}:{}: Lexing error", src_path, line + 1),
+            Error::ParseSyntaxError { error, .. } => write!(f, "error: {}", error)?,
+            Error::LexError { .. } => write!(f, "error: lexing error")?,
+            Error::MacroError {
+                macro_name,
+                message,
+                ..
+            } => write!(
+                f,
+                "error while parsing the `{}` macro: {}",
+                macro_name, message
+            )?,
+            Error::ModuleNotFound { name, .. } => {
+                write!(f, "error: file not found for module `{}`", name)?
+            }
+        }
+        if let Some(location) = self.location() {
+            let margin = " ".repeat(location.line.to_string().len());
+            write!(
+                f,
+                "\n{margin}--> {}:{}:{}\n{margin} |\n{} | {}\n{margin} | {}^",
+                location.src_path,
+                location.line,
+                location.column,
+                location.line,
+                location.snippet,
+                " ".repeat(location.column as usize - 1),
+                margin = margin
+            )?;
         }
+        Ok(())
     }
 }
 
+/// An error at a 0-based line, and column if it is known, of a source file
 #[derive(Debug)]
-struct LineError(u32, String);
+struct LineError(u32, Option<u32>, String);
 
 impl LineError {
     fn add_line(self, a: u32) -> LineError {
-        LineError(self.0 + a, self.1)
+        LineError(self.0 + a, self.1, self.2)
     }
-}
 
-impl fmt::Display for LineError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}:{}", self.0 + 1, self.1)
+    /// The error of parsing the tokens which start at the cursor
+    fn from_syn(x: Cursor, e: syn::Error) -> LineError {
+        let start = e.span().start();
+        let (line, column) = match start.line {
+            0 => (x.line, x.column),
+            1 => (x.line, x.column + start.column as u32),
+            l => (x.line + l as u32 - 1, start.column as u32),
+        };
+        LineError(line, Some(column), e.to_string())
     }
 }
 
 impl From<LexError> for LineError {
     fn from(e: LexError) -> Self {
-        LineError(e.line, "Lexing error".into())
+        LineError(e.line, None, "Lexing error".into())
     }
 }
 
@@ -114,52 +194,64 @@ fn expand_sub_rust_macro(input: String, mut t: ExpandSubMacroType) -> Result<Str
         };
         let input: ::proc_macro2::TokenStream = result[begin..end]
             .parse()
-            .map_err(|_| LineError(line, "TokenStream parse error".into()))?;
-        let rust_invocation =
-            ::syn::parse2::<RustInvocation>(input).map_err(|e| LineError(line, e.to_string()))?;
+            .map_err(|_| LineError(line, None, "TokenStream parse error".into()))?;
+        let rust_invocation = ::syn::parse2::<RustInvocation>(input)
+            .map_err(|e| LineError(line, None, e.to_string()))?;
         let fn_name = match t {
             ExpandSubMacroType::Lit => {
                 extra_decl.push_str(&format!("extern \"C\" void {}();\n", rust_invocation.id));
                 rust_invocation.id.clone().to_string()
             }
             ExpandSubMacroType::Closure(ref mut offset) => {
-                use cpp_common::FILE_HASH;
+                // The name of the array of callbacks depends on the OUT_DIR
+                let file_hash = cpp_common::file_hash().ok_or_else(|| {
+                    LineError(
+                        line,
+                        None,
+                        "the OUT_DIR environment variable must be set to expand the rust! macros"
+                            .into(),
+                    )
+                })?;
                 **offset += 1;
                 format!(
                     "rust_cpp_callbacks{file_hash}[{offset}]",
-                    file_hash = *FILE_HASH,
+                    file_hash = file_hash,
                     offset = **offset - 1
                 )
             }
         };
 
-        let mut decl_types = rust_invocation
-            .arguments
-            .iter()
-            .map(|&(_, ref val)| format!("rustcpp::argument_helper<{}>::type", val))
-            .collect::<Vec<_>>();
+        let mut decl_types = vec!["rustcpp::rust_panic_info*".to_owned()];
+        decl_types.extend(
+            rust_invocation
+                .arguments
+                .iter()
+                .map(|(_, val)| format!("rustcpp::argument_helper<{}>::type", val)),
+        );
         let mut call_args = rust_invocation
             .arguments
             .iter()
-            .map(|&(ref val, _)| val.to_string())
+            .map(|(val, _)| format!(", {}", val))
             .collect::<Vec<_>>();
 
         let fn_call = match rust_invocation.return_type {
             None => format!(
-                "reinterpret_cast<void (*)({types})>({f})({args})",
+                "rustcpp::call_rust_void(reinterpret_cast<void (*)({types})>({f}){args})",
                 f = fn_name,
                 types = decl_types.join(", "),
-                args = call_args.join(", ")
+                args = call_args.concat()
             ),
             Some(rty) => {
+                // The return_helper must be created here, so that its container
+                // lives until the end of the full expression.
                 decl_types.push(format!("rustcpp::return_helper<{rty}>", rty = rty));
-                call_args.push("0".to_string());
+                call_args.push(format!(", rustcpp::return_helper<{rty}>(0)", rty = rty));
                 format!(
-                    "std::move(*reinterpret_cast<{rty}*(*)({types})>({f})({args}))",
+                    "std::move(*rustcpp::call_rust(reinterpret_cast<{rty}*(*)({types})>({f}){args}))",
                     rty = rty,
                     f = fn_name,
                     types = decl_types.join(", "),
-                    args = call_args.join(", ")
+                    args = call_args.concat()
                 )
             }
         };
@@ -188,14 +280,14 @@ fn test_expand_sub_rust_macro() {
     );
     assert_eq!(
         x.unwrap(),
-        "extern \"C\" void xxx();\n{ reinterpret_cast<void (*)()>(xxx)(); }"
+        "extern \"C\" void xxx();\n{ rustcpp::call_rust_void(reinterpret_cast<void (*)(rustcpp::rust_panic_info*)>(xxx)); }"
     );
 
     let x = expand_sub_rust_macro(
         "{ hello( rust!(xxx [] { 1 }), rust!(yyy [] { 2 }); ) }".to_owned(),
         ExpandSubMacroType::Lit,
     );
-    assert_eq!(x.unwrap(), "extern \"C\" void xxx();\nextern \"C\" void yyy();\n{ hello( reinterpret_cast<void (*)()>(xxx)(), reinterpret_cast<void (*)()>(yyy)(); ) }");
+    assert_eq!(x.unwrap(), "extern \"C\" void xxx();\nextern \"C\" void yyy();\n{ hello( rustcpp::call_rust_void(reinterpret_cast<void (*)(rustcpp::rust_panic_info*)>(xxx)), rustcpp::call_rust_void(reinterpret_cast<void (*)(rustcpp::rust_panic_info*)>(yyy)); ) }");
 
     let s = "{ /* rust! */  /* rust!(xxx [] { 1 }) */ }".to_owned();
     assert_eq!(
@@ -204,8 +296,6 @@ fn test_expand_sub_rust_macro() {
     );
 }
 
-#[path = "strnom.rs"]
-mod strnom;
 use crate::strnom::*;
 
 fn skip_literal(mut input: Cursor) -> PResult<bool> {
@@ -246,7 +336,7 @@ fn skip_literal(mut input: Cursor) -> PResult<bool> {
     Ok((input, false))
 }
 
-fn new_cursor(s: &str) -> Cursor {
+fn new_cursor(s: &str) -> Cursor<'_> {
     Cursor {
         rest: s,
         off: 0,
@@ -335,7 +425,7 @@ fn test_cursor_advance() -> Result<(), LexError> {
     Ok(())
 }
 
-fn line_directive(path: &PathBuf, cur: Cursor) -> String {
+fn line_directive(path: &Path, cur: Cursor) -> String {
     let mut line = format!(
         "#line {} \"{}\"\n",
         cur.line + 1,
@@ -347,23 +437,44 @@ fn line_directive(path: &PathBuf, cur: Cursor) -> String {
     line
 }
 
+/// The C++ code found in one source file of the crate
 #[derive(Default)]
-pub struct Parser {
+pub struct Module {
+    pub path: PathBuf,
     pub closures: Vec<Closure>,
     pub classes: Vec<Class>,
     pub snippets: String,
+}
+
+#[derive(Default)]
+pub struct Parser {
+    pub modules: Vec<Module>, // In the order in which they were first found
+    pub files: Vec<PathBuf>,  // All the files which were parsed
     pub callbacks_count: u32,
     current_path: PathBuf, // The current file being parsed
     mod_dir: PathBuf,
     mod_error: Option<Error>, // An error occuring while visiting the modules
+    in_expansion: bool,       // Whether the code being parsed is a macro_rules! expansion
+    macro_rules: HashMap<String, MacroRules>,
+    macro_invocations: Vec<MacroInvocation>,
+}
+
+/// The invocation of a macro which might be a macro_rules! macro generating cpp! macros
+struct MacroInvocation {
+    name: String,
+    tokens: String,
+    path: PathBuf,
+    line: u32,
 }
 
 impl Parser {
     pub fn parse_crate<P: AsRef<Path>>(&mut self, crate_root: P) -> Result<(), Error> {
-        self.parse_mod(crate_root)
+        self.parse_mod(crate_root)?;
+        self.expand_macros()
     }
 
     fn parse_mod<P: AsRef<Path>>(&mut self, mod_path: P) -> Result<(), Error> {
+        self.files.push(mod_path.as_ref().to_owned());
         let mut s = String::new();
         let mut f = File::open(&mod_path).map_err(|_| Error::ParseCannotOpenFile {
             src_path: mod_path.as_ref().to_str().unwrap().to_owned(),
@@ -373,18 +484,31 @@ impl Parser {
                 src_path: mod_path.as_ref().to_str().unwrap().to_owned(),
             })?;
 
-        let fi = syn::parse_file(&s).map_err(|x| Error::ParseSyntaxError {
-            src_path: mod_path.as_ref().to_str().unwrap().to_owned(),
-            error: x,
+        let fi = syn::parse_file(&s).map_err(|x| {
+            let start = x.span().start();
+            Error::ParseSyntaxError {
+                location: Location::new(
+                    mod_path.as_ref(),
+                    start.line.saturating_sub(1) as u32,
+                    Some(start.column as u32),
+                ),
+                error: x,
+            }
         })?;
 
+        // An inner `#![cfg(...)]` attribute disables the whole file
+        if !cfg::is_enabled(&fi.attrs) {
+            return Ok(());
+        }
+        let disabled_ranges = cfg::disabled_ranges(&fi, &s);
+
         let mut current_path = mod_path.as_ref().into();
         let mut mod_dir = mod_path.as_ref().parent().unwrap().into();
 
         swap(&mut self.current_path, &mut current_path);
         swap(&mut self.mod_dir, &mut mod_dir);
 
-        self.find_cpp_macros(&s)?;
+        self.find_cpp_macros(new_cursor(&s), &disabled_ranges)?;
         self.visit_file(&fi);
         if let Some(err) = self.mod_error.take() {
             return Err(err);
@@ -427,8 +551,11 @@ impl Parser {
     }
     */
 
-    fn find_cpp_macros(&mut self, source: &str) -> Result<(), Error> {
-        let mut cursor = new_cursor(source);
+    fn find_cpp_macros(
+        &mut self,
+        mut cursor: Cursor,
+        disabled: &[Range<usize>],
+    ) -> Result<(), Error> {
         while !cursor.is_empty() {
             cursor = skip_whitespace(cursor);
             let r = skip_literal(cursor).map_err(|e| self.lex_error(e))?;
@@ -437,45 +564,69 @@ impl Parser {
                 continue;
             }
             if let Ok((cur, ident)) = symbol(cursor) {
+                let begin = cursor.off as usize;
                 cursor = cur;
-                if ident != "cpp" && ident != "cpp_class" {
+                let mut macro_cur = skip_whitespace(cursor);
+                if !macro_cur.starts_with("!") {
                     continue;
                 }
-                cursor = skip_whitespace(cursor);
-                if !cursor.starts_with("!") {
-                    continue;
+                macro_cur = skip_whitespace(macro_cur.advance(1));
+                let mut macro_rules_name = None;
+                if ident == "macro_rules" {
+                    if let Ok((cur, name)) = symbol(macro_cur) {
+                        macro_rules_name = Some(name);
+                        macro_cur = skip_whitespace(cur);
+                    }
                 }
-                cursor = skip_whitespace(cursor.advance(1));
-                let delim = if cursor.starts_with("(") {
+                let delim = if macro_cur.starts_with("(") {
                     ")"
-                } else if cursor.starts_with("[") {
+                } else if macro_cur.starts_with("[") {
                     "]"
-                } else if cursor.starts_with("{") {
+                } else if macro_cur.starts_with("{") {
                     "}"
                 } else {
                     continue;
                 };
-                cursor = cursor.advance(1);
-                let mut macro_cur = cursor;
-                cursor = find_delimited(cursor, delim)
-                    .map_err(|e| self.lex_error(e))?
-                    .0;
-                let size = (cursor.off - macro_cur.off) as usize;
+                macro_cur = macro_cur.advance(1);
+                let is_cpp = ident == "cpp" || ident == "cpp_class";
+                let end = match find_delimited(macro_cur, delim) {
+                    Ok((end, _)) => end,
+                    Err(e) if is_cpp => return Err(self.lex_error(e)),
+                    Err(_) => continue,
+                };
+                let size = (end.off - macro_cur.off) as usize;
                 macro_cur.rest = &macro_cur.rest[..size];
+                // Skip macros within items disabled by a #[cfg(...)] attribute
+                let enabled = !disabled.iter().any(|r| r.contains(&begin));
                 if ident == "cpp" {
-                    self.handle_cpp(macro_cur).unwrap_or_else(|e| {
-                        panic!(
-                            "Error while parsing cpp! macro:\n{:?}:{}",
-                            self.current_path, e
-                        )
-                    });
-                } else {
-                    debug_assert_eq!(ident, "cpp_class");
-                    self.handle_cpp_class(macro_cur).unwrap_or_else(|e| {
-                        panic!(
-                            "Error while parsing cpp_class! macro:\n{:?}:{}",
-                            self.current_path, e
-                        )
+                    cursor = end;
+                    if enabled {
+                        self.handle_cpp(macro_cur)
+                            .map_err(|e| self.macro_error("cpp!", e))?;
+                    }
+                } else if ident == "cpp_class" {
+                    cursor = end;
+                    if enabled {
+                        self.handle_cpp_class(macro_cur)
+                            .map_err(|e| self.macro_error("cpp_class!", e))?;
+                    }
+                } else if let Some(name) = macro_rules_name {
+                    // The body of a macro_rules! is only a template: the cpp! macros within
+                    // it are found when expanding its invocations.
+                    cursor = end;
+                    if let Some(rules) = macro_cur.rest.parse().ok().and_then(MacroRules::parse) {
+                        if enabled {
+                            self.macro_rules.insert(name.to_owned(), rules);
+                        }
+                    }
+                } else if enabled && ident != "rust" {
+                    // Continue searching for cpp! macros inside the arguments of this macro,
+                    // but also remember the invocation in case it is a macro_rules! macro.
+                    self.macro_invocations.push(MacroInvocation {
+                        name: ident.to_owned(),
+                        tokens: macro_cur.rest.to_owned(),
+                        path: self.current_path.clone(),
+                        line: macro_cur.line,
                     });
                 }
                 continue;
@@ -488,10 +639,70 @@ impl Parser {
         Ok(())
     }
 
+    /// Expand the invocations of the macro_rules! macros which may generate cpp! macros,
+    /// and parse the cpp! macros from the expansion.
+    fn expand_macros(&mut self) -> Result<(), Error> {
+        // The expansion may contain other macro invocations, which are expanded in the
+        // next iteration, up to this limit.
+        const RECURSION_LIMIT: usize = 64;
+        for _ in 0..RECURSION_LIMIT {
+            let invocations = take(&mut self.macro_invocations);
+            if invocations.is_empty() {
+                break;
+            }
+            let macros_with_cpp = expand::macros_with_cpp(&self.macro_rules);
+            for invocation in invocations {
+                if !macros_with_cpp.contains(&invocation.name) {
+                    continue;
+                }
+                let expansion = match invocation
+                    .tokens
+                    .parse()
+                    .ok()
+                    .and_then(|tokens| self.macro_rules[&invocation.name].expand(tokens))
+                {
+                    Some(expansion) => expansion.to_string(),
+                    // Let rustc report the error
+                    None => continue,
+                };
+                let mut current_path = invocation.path;
+                swap(&mut self.current_path, &mut current_path);
+                // The expansion is on a single line: make the #line directives point to
+                // the invocation
+                let cursor = Cursor {
+                    rest: &expansion,
+                    off: 0,
+                    line: invocation.line,
+                    column: 0,
+                };
+                self.in_expansion = true;
+                let result = self.find_cpp_macros(cursor, &[]);
+                self.in_expansion = false;
+                swap(&mut self.current_path, &mut current_path);
+                result?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The location of a 0-based line and column in the current file. The
+    /// columns are not meaningful within the expansion of a macro_rules! macro.
+    fn location(&self, line: u32, column: Option<u32>) -> Location {
+        let column = column.filter(|_| !self.in_expansion);
+        Location::new(&self.current_path, line, column)
+    }
+
     fn lex_error(&self, e: LexError) -> Error {
         Error::LexError {
-            src_path: self.current_path.clone().to_str().unwrap().to_owned(),
-            line: e.line,
+            location: self.location(e.line, None),
+        }
+    }
+
+    fn macro_error(&self, macro_name: &'static str, e: LineError) -> Error {
+        Error::MacroError {
+            location: self.location(e.0, e.1),
+            macro_name,
+            message: e.2,
         }
     }
 
@@ -504,8 +715,8 @@ impl Parser {
         let input: ::proc_macro2::TokenStream = x
             .rest
             .parse()
-            .map_err(|_| LineError(x.line, "TokenStream parse error".into()))?;
-        match ::syn::parse2::<Macro>(input).map_err(|e| LineError(x.line, e.to_string()))? {
+            .map_err(|_| LineError(x.line, Some(x.column), "TokenStream parse error".into()))?;
+        match ::syn::parse2::<Macro>(input).map_err(|e| LineError::from_syn(x, e))? {
             Macro::Closure(mut c) => {
                 c.callback_offset = self.callbacks_count;
                 c.body_str = line_directive(&self.current_path, begin)
@@ -514,16 +725,17 @@ impl Parser {
                         ExpandSubMacroType::Closure(&mut self.callbacks_count),
                     )
                     .map_err(|e| e.add_line(begin.line))?;
-                self.closures.push(c);
+                self.current_module().closures.push(c);
             }
             Macro::Lit(_l) => {
-                self.snippets.push('\n');
                 let snip = expand_sub_rust_macro(
                     line_directive(&self.current_path, begin) + extracted,
                     ExpandSubMacroType::Lit,
                 )
                 .map_err(|e| e.add_line(begin.line))?;
-                self.snippets.push_str(&snip);
+                let snippets = &mut self.current_module().snippets;
+                snippets.push('\n');
+                snippets.push_str(&snip);
             }
         }
         Ok(())
@@ -533,13 +745,39 @@ impl Parser {
         let input: ::proc_macro2::TokenStream = x
             .rest
             .parse()
-            .map_err(|_| LineError(x.line, "TokenStream parse error".into()))?;
-        let mut class =
-            ::syn::parse2::<Class>(input).map_err(|e| LineError(x.line, e.to_string()))?;
+            .map_err(|_| LineError(x.line, Some(x.column), "TokenStream parse error".into()))?;
+        let mut class = ::syn::parse2::<Class>(input).map_err(|e| LineError::from_syn(x, e))?;
+        class.operators().map_err(|e| LineError::from_syn(x, e))?;
         class.line = line_directive(&self.current_path, x);
-        self.classes.push(class);
+        // The same class may be found both in a macro invocation and in its expansion
+        if !self
+            .modules
+            .iter()
+            .flat_map(|m| &m.classes)
+            .any(|c| c.name_hash() == class.name_hash())
+        {
+            self.current_module().classes.push(class);
+        }
         Ok(())
     }
+
+    fn current_module(&mut self) -> &mut Module {
+        let index = match self
+            .modules
+            .iter()
+            .position(|m| m.path == self.current_path)
+        {
+            Some(index) => index,
+            None => {
+                self.modules.push(Module {
+                    path: self.current_path.clone(),
+                    ..Default::default()
+                });
+                self.modules.len() - 1
+            }
+        };
+        &mut self.modules[index]
+    }
 }
 
 impl<'ast> Visit<'ast> for Parser {
@@ -563,6 +801,11 @@ impl<'ast> Visit<'ast> for Parser {
             return;
         }
 
+        // Don't follow modules which are not enabled in the current configuration
+        if !cfg::is_enabled(&item.attrs) {
+            return;
+        }
+
         if item.content.is_some() {
             let mut parent = self.mod_dir.join(item.ident.to_string());
             swap(&mut self.mod_dir, &mut parent);
@@ -573,42 +816,19 @@ impl<'ast> Visit<'ast> for Parser {
 
         // Determine the path of the inner module's file
         for attr in &item.attrs {
-            match attr.parse_meta() {
-                // parse #[path = "foo.rs"]: read module from the specified path
-                Ok(syn::Meta::NameValue(syn::MetaNameValue {
-                    ref path,
-                    lit: syn::Lit::Str(ref s),
-                    ..
-                })) if path.is_ident("path") => {
-                    let mod_path = self.mod_dir.join(&s.value());
+            // parse #[path = "foo.rs"]: read module from the specified path
+            if let Ok(syn::Meta::NameValue(syn::MetaNameValue {
+                ref path,
+                lit: syn::Lit::Str(ref s),
+                ..
+            })) = attr.parse_meta()
+            {
+                if path.is_ident("path") {
+                    let mod_path = self.mod_dir.join(s.value());
                     return self
                         .parse_mod(mod_path)
                         .unwrap_or_else(|err| self.mod_error = Some(err));
                 }
-                // parse #[cfg(feature = "feature")]: don't follow modules not enabled by current features
-                Ok(syn::Meta::List(syn::MetaList {
-                    ref path,
-                    ref nested,
-                    ..
-                })) if path.is_ident("cfg") => {
-                    for n in nested {
-                        match n {
-                            syn::NestedMeta::Meta(syn::Meta::NameValue(syn::MetaNameValue {
-                                path,
-                                lit: syn::Lit::Str(feature),
-                                ..
-                            })) if path.is_ident("feature") => {
-                                let feature_env_var = "CARGO_FEATURE_".to_owned()
-                                    + &feature.value().to_uppercase().replace("-", "_");
-                                if std::env::var_os(feature_env_var).is_none() {
-                                    return;
-                                }
-                            }
-                            _ => {}
-                        }
-                    }
-                }
-                _ => {}
             }
         }
 
@@ -633,16 +853,64 @@ impl<'ast> Visit<'ast> for Parser {
             }
         }
 
-        let adjacent = self.mod_dir.join(&format!("{}.rs", mod_name));
+        let adjacent = self.mod_dir.join(format!("{}.rs", mod_name));
         if adjacent.is_file() {
             return self
                 .parse_mod(adjacent)
                 .unwrap_or_else(|err| self.mod_error = Some(err));
         }
 
-        panic!(
-            "No file with module definition for `mod {}` in file {:?}",
-            mod_name, self.current_path
-        );
+        let start = item.ident.span().start();
+        self.mod_error = Some(Error::ModuleNotFound {
+            location: self.location(
+                start.line.saturating_sub(1) as u32,
+                Some(start.column as u32),
+            ),
+            name: mod_name,
+        });
     }
 }
+
+#[test]
+fn test_located_errors() {
+    let test_crate = crate::test_crate::TestCrate::new("located_errors");
+    let parse = |name: &str, code: &str| {
+        let path = test_crate.write(name, code);
+        Parser::default().parse_crate(path).unwrap_err()
+    };
+
+    let err = parse(
+        "macro.rs",
+        "fn foo() {\n    cpp!{[x as \"int\"] -> 42 as \"int\" {}}\n}\n",
+    );
+    let location = err.location().unwrap();
+    assert!(matches!(
+        err,
+        Error::MacroError {
+            macro_name: "cpp!",
+            ..
+        }
+    ));
+    assert_eq!((location.line, location.column), (2, 26));
+    assert_eq!(
+        location.snippet,
+        "    cpp!{[x as \"int\"] -> 42 as \"int\" {}}"
+    );
+
+    let err = parse("module.rs", "// comment\nmod missing;\n");
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "error: file not found for module `missing`\n --> {}:2:5\n  |\n2 | mod missing;\n  |     ^",
+            test_crate.dir.join("module.rs").display()
+        )
+    );
+
+    let err = parse(
+        "operators.rs",
+        "cpp_class!(\n    #[cpp(index(usize as \"size_t\") -> f64 as \"double\")]\n    #[cpp(index_mut(u64 as \"size_t\") -> f64 as \"double\")]\n    unsafe struct A as \"A\"\n);\n",
+    );
+    assert!(err
+        .to_string()
+        .contains("this operator is already declared for this cpp_class!"));
+}
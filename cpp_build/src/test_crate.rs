@@ -0,0 +1,33 @@
+//! Crates written to a temporary directory, for the tests which parse them.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// A temporary directory containing the source files of a crate, removed when
+/// dropped.
+pub struct TestCrate {
+    pub dir: PathBuf,
+}
+
+impl TestCrate {
+    /// Create the directory, named after the test
+    pub fn new(name: &str) -> TestCrate {
+        let dir = env::temp_dir().join(format!("rust_cpp_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        TestCrate { dir }
+    }
+
+    /// Write a source file of the crate, and return its path
+    pub fn write(&self, name: &str, code: &str) -> PathBuf {
+        let path = self.dir.join(name);
+        fs::write(&path, code).unwrap();
+        path
+    }
+}
+
+impl Drop for TestCrate {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
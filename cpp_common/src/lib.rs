@@ -15,11 +15,14 @@ use std::env;
 use std::hash::{Hash, Hasher};
 use std::path::PathBuf;
 
-use proc_macro2::{Span, TokenStream, TokenTree};
+use proc_macro2::{Delimiter, Group, Span, TokenStream, TokenTree};
+use quote::ToTokens;
 use syn::ext::IdentExt;
 use syn::parse::{Parse, ParseStream, Result};
 use syn::{Attribute, Ident, Type};
 
+pub mod metadata;
+
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 pub const LIB_NAME: &str = "librust_cpp_generated.a";
@@ -31,20 +34,36 @@ pub mod flags {
     pub const IS_TRIVIALLY_DESTRUCTIBLE: u32 = 2;
     pub const IS_TRIVIALLY_COPYABLE: u32 = 3;
     pub const IS_TRIVIALLY_DEFAULT_CONSTRUCTIBLE: u32 = 4;
+    /// Set on the entry of a constructor declared in a `cpp_class!` when the
+    /// class is constructible from the arguments
+    pub const IS_CONSTRUCTIBLE: u32 = 5;
+    pub const IS_MOVE_CONSTRUCTIBLE: u32 = 6;
+    pub const IS_COPY_ASSIGNABLE: u32 = 7;
+    pub const IS_MOVE_ASSIGNABLE: u32 = 8;
+    /// Set when `rustcpp::is_relocatable<T>` is true
+    pub const IS_TRIVIALLY_RELOCATABLE: u32 = 9;
 }
 
 pub mod kw {
     #![allow(non_camel_case_types)]
     custom_keyword!(rust);
+    custom_keyword!(pinned);
+    custom_keyword!(boxed);
 }
 
-/// This constant is expected to be a unique string within the compiled binary
-/// which precedes a definition of the metadata. It begins with
-/// rustcpp~metadata, which is printable to make it easier to locate when
-/// looking at a binary dump of the metadata.
-///
-/// NOTE: In the future we may want to use a object file parser and a custom
-/// section rather than depending on this string being unique.
+/// The name of the object file section containing the metadata.
+/// Mach-O section names are prefixed by two underscores instead of a dot.
+pub const METADATA_SECTION: &str = ".rustcpp_meta";
+pub const MACHO_METADATA_SECTION: &str = "__rustcpp_meta";
+
+/// Returns true if `name` is the name of a metadata section in an object file.
+pub fn is_metadata_section(name: &str) -> bool {
+    name == METADATA_SECTION || name == MACHO_METADATA_SECTION
+}
+
+/// This constant begins each definition of the metadata within the metadata
+/// section. It begins with rustcpp~metadata, which is printable to make it
+/// easier to locate when looking at a binary dump of the metadata.
 #[rustfmt::skip]
 pub const STRUCT_METADATA_MAGIC: [u8; 128] = [
     b'r', b'u', b's', b't', b'c', b'p', b'p', b'~',
@@ -73,6 +92,12 @@ NOTE: rustc must be run by Cargo."#
     };
 }
 
+/// The value of `FILE_HASH`, or `None` if the OUT_DIR environment variable is
+/// not set, for the tools which run outside of a build.
+pub fn file_hash() -> Option<u64> {
+    env::var_os("OUT_DIR").map(|_| *FILE_HASH)
+}
+
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
 pub struct Capture {
     pub mutable: bool,
@@ -95,12 +120,54 @@ impl Parse for Capture {
     }
 }
 
-#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ClosureSig {
     pub captures: Vec<Capture>,
     pub ret: Option<Type>,
     pub cpp: String,
     pub std_body: String,
+    pub exception: bool, // `try` closure: C++ exceptions are returned as a `CppException`
+}
+
+/// Remove the invisible groups with which rustc wraps the `expr` and `ty`
+/// fragments substituted by `macro_rules!`. The build script expands the
+/// macros from their text, so it does not see these groups.
+pub fn flatten_none_groups(tokens: TokenStream) -> TokenStream {
+    tokens
+        .into_iter()
+        .flat_map(|tt| match tt {
+            TokenTree::Group(g) if g.delimiter() == Delimiter::None => {
+                flatten_none_groups(g.stream())
+            }
+            TokenTree::Group(g) => {
+                let mut group = Group::new(g.delimiter(), flatten_none_groups(g.stream()));
+                group.set_span(g.span());
+                TokenStream::from(TokenTree::Group(group))
+            }
+            tt => TokenStream::from(tt),
+        })
+        .collect()
+}
+
+impl Hash for ClosureSig {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.captures.hash(state);
+        // Like `std_body`, without the spaces which differ between proc_macro
+        // and proc_macro2
+        self.ret
+            .as_ref()
+            .map(|t| {
+                flatten_none_groups(t.to_token_stream())
+                    .to_string()
+                    .chars()
+                    .filter(|x| !x.is_whitespace())
+                    .collect::<String>()
+            })
+            .hash(state);
+        self.cpp.hash(state);
+        self.std_body.hash(state);
+        self.exception.hash(state);
+    }
 }
 
 impl ClosureSig {
@@ -130,8 +197,10 @@ pub struct Closure {
 impl Parse for Closure {
     /// Parse the inside of a `cpp!` macro when this macro is a closure.
     /// Example: `unsafe [foo as "int"] -> u32 as "int" { /*... */ }
+    /// or `try [foo as "int"] -> Result<u32, CppException> as "int" { /*... */ }`
     fn parse(input: ParseStream) -> Result<Self> {
         input.parse::<Option<Token![unsafe]>>()?;
+        let exception = input.parse::<Option<Token![try]>>()?.is_some();
 
         // Capture
         let capture_content;
@@ -155,7 +224,7 @@ impl Parse for Closure {
         let body = input.parse::<TokenTree>()?;
         // Need to filter the spaces because there is a difference between
         // proc_macro2 and proc_macro and the hashes would not match
-        let std_body = body
+        let std_body = flatten_none_groups(body.clone().into())
             .to_string()
             .chars()
             .filter(|x| !x.is_whitespace())
@@ -167,6 +236,7 @@ impl Parse for Closure {
                 ret,
                 cpp,
                 std_body,
+                exception,
             },
             body,
             body_str: String::new(),
@@ -175,11 +245,191 @@ impl Parse for Closure {
     }
 }
 
+/// The operator traits which can be derived by a `cpp_class!`, with their
+/// method and the corresponding C++ operator. The binary operators take and
+/// return `Self`.
+pub const BINARY_OPERATORS: [(&str, &str, &str); 8] = [
+    ("Add", "add", "+"),
+    ("Sub", "sub", "-"),
+    ("Mul", "mul", "*"),
+    ("Div", "div", "/"),
+    ("Rem", "rem", "%"),
+    ("BitAnd", "bitand", "&"),
+    ("BitOr", "bitor", "|"),
+    ("BitXor", "bitxor", "^"),
+];
+pub const ASSIGN_OPERATORS: [(&str, &str, &str); 8] = [
+    ("AddAssign", "add_assign", "+="),
+    ("SubAssign", "sub_assign", "-="),
+    ("MulAssign", "mul_assign", "*="),
+    ("DivAssign", "div_assign", "/="),
+    ("RemAssign", "rem_assign", "%="),
+    ("BitAndAssign", "bitand_assign", "&="),
+    ("BitOrAssign", "bitor_assign", "|="),
+    ("BitXorAssign", "bitxor_assign", "^="),
+];
+pub const UNARY_OPERATORS: [(&str, &str, &str); 1] = [("Neg", "neg", "-")];
+
+/// An operator of a `cpp_class!` which involves other types, declared with a
+/// `#[cpp(...)]` attribute.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug)]
+pub enum ClassOperator {
+    /// `#[cpp(index(usize as "size_t") -> f64 as "double")]` implements
+    /// `Index<usize>` with `operator[]`. `index_mut` implements `IndexMut` as well.
+    Index {
+        mutable: bool,
+        index: Type,
+        index_cpp: String,
+        output: Type,
+        output_cpp: String,
+    },
+    /// `#[cpp(deref -> Target as "Target")]` implements `Deref` with the unary
+    /// `operator*`. `deref_mut` implements `DerefMut` as well.
+    Deref {
+        mutable: bool,
+        target: Type,
+        target_cpp: String,
+    },
+}
+
+impl Parse for ClassOperator {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let kind = input.parse::<Ident>()?;
+        match kind.to_string().as_str() {
+            "index" | "index_mut" => {
+                let content;
+                parenthesized!(content in input);
+                let index = content.parse()?;
+                content.parse::<Token![as]>()?;
+                let index_cpp = content.parse::<syn::LitStr>()?.value();
+                input.parse::<Token![->]>()?;
+                let output = input.parse()?;
+                input.parse::<Token![as]>()?;
+                Ok(ClassOperator::Index {
+                    mutable: kind == "index_mut",
+                    index,
+                    index_cpp,
+                    output,
+                    output_cpp: input.parse::<syn::LitStr>()?.value(),
+                })
+            }
+            "deref" | "deref_mut" => {
+                input.parse::<Token![->]>()?;
+                let target = input.parse()?;
+                input.parse::<Token![as]>()?;
+                Ok(ClassOperator::Deref {
+                    mutable: kind == "deref_mut",
+                    target,
+                    target_cpp: input.parse::<syn::LitStr>()?.value(),
+                })
+            }
+            _ => Err(syn::Error::new(
+                kind.span(),
+                "expected `index`, `index_mut`, `deref` or `deref_mut`",
+            )),
+        }
+    }
+}
+
+/// How a member function declared in a `cpp_class!` receives the object.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Receiver {
+    /// A static member function
+    None,
+    /// `&self`, a const member function
+    Ref,
+    /// `&mut self`
+    Mut,
+}
+
+#[derive(Clone, Debug)]
+pub struct MemberArg {
+    pub name: Ident,
+    pub ty: Type,
+    pub cpp: String,
+}
+
+/// A member function or a constructor declared in the body of a `cpp_class!`.
+/// Examples: `fn set_values(&mut self, a: i32 as "int", b: i32 as "int");`
+/// or, for a constructor, `new(a: i32 as "int", b: i32 as "int");`
+#[derive(Clone, Debug)]
+pub struct Member {
+    pub attrs: Vec<Attribute>,
+    pub vis: syn::Visibility,
+    pub constructor: bool,
+    pub name: Ident,
+    pub receiver: Receiver,
+    pub args: Vec<MemberArg>,
+    pub ret: Option<(Type, String)>,
+}
+
+impl Parse for Member {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let attrs = input.call(Attribute::parse_outer)?;
+        let vis = input.parse()?;
+        let constructor = input.parse::<Option<Token![fn]>>()?.is_none();
+        let name = input.parse()?;
+        let content;
+        parenthesized!(content in input);
+        let receiver = if !constructor && content.peek(Token![&]) {
+            content.parse::<Token![&]>()?;
+            let mutable = content.parse::<Option<Token![mut]>>()?.is_some();
+            content.parse::<Token![self]>()?;
+            if !content.is_empty() {
+                content.parse::<Token![,]>()?;
+            }
+            if mutable {
+                Receiver::Mut
+            } else {
+                Receiver::Ref
+            }
+        } else {
+            Receiver::None
+        };
+        let mut args = Vec::new();
+        while !content.is_empty() {
+            let name = content.parse()?;
+            content.parse::<Token![:]>()?;
+            let ty = content.parse()?;
+            content.parse::<Token![as]>()?;
+            let cpp = content.parse::<syn::LitStr>()?.value();
+            args.push(MemberArg { name, ty, cpp });
+            if !content.is_empty() {
+                content.parse::<Token![,]>()?;
+            }
+        }
+        let ret = if !constructor && input.parse::<Option<Token![->]>>()?.is_some() {
+            let ty = input.parse()?;
+            input.parse::<Token![as]>()?;
+            Some((ty, input.parse::<syn::LitStr>()?.value()))
+        } else {
+            None
+        };
+        input.parse::<Token![;]>()?;
+        Ok(Member {
+            attrs,
+            vis,
+            constructor,
+            name,
+            receiver,
+            args,
+            ret,
+        })
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Class {
     pub name: Ident,
     pub cpp: String,
     pub attrs: Vec<Attribute>,
+    /// Whether the class was declared with `pinned`, for non-relocatable types
+    pub pinned: bool,
+    /// Whether the class was declared with `boxed`: the Rust struct is then an
+    /// owning pointer to the C++ object
+    pub boxed: bool,
+    pub members: Vec<Member>,
     pub line: String, // the #line directive
 }
 
@@ -191,6 +441,22 @@ impl Class {
         hasher.finish()
     }
 
+    /// The hash identifying a member function in the metadata
+    pub fn member_hash(&self, member: &Member) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.name_hash().hash(&mut hasher);
+        member.name.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// The name of the C++ function calling a member function
+    pub fn member_extern_name(&self, member: &Member) -> Ident {
+        Ident::new(
+            &format!("__cpp_member_{}", self.member_hash(member)),
+            Span::call_site(),
+        )
+    }
+
     pub fn derives(&self, i: &str) -> bool {
         self.attrs.iter().any(|x| {
             use syn::{Meta, NestedMeta};
@@ -212,22 +478,74 @@ impl Class {
     }
 }
 
+impl Class {
+    /// The operators declared with `#[cpp(...)]` attributes.
+    /// Each of them has its own helper, so an operator may only be declared
+    /// once for a given index type.
+    pub fn operators(&self) -> Result<Vec<ClassOperator>> {
+        let mut operators: Vec<ClassOperator> = Vec::new();
+        for attr in self.attrs.iter().filter(|attr| attr.path.is_ident("cpp")) {
+            let operator = attr.parse_args()?;
+            let hash = self.operator_hash(&operator);
+            if operators.iter().any(|o| self.operator_hash(o) == hash) {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    "this operator is already declared for this cpp_class!",
+                ));
+            }
+            operators.push(operator);
+        }
+        Ok(operators)
+    }
+
+    /// The hash identifying an operator declared with `#[cpp(...)]` in the
+    /// metadata, and naming its helpers
+    pub fn operator_hash(&self, operator: &ClassOperator) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.name_hash().hash(&mut hasher);
+        match operator {
+            ClassOperator::Index { index_cpp, .. } => {
+                "index".hash(&mut hasher);
+                index_cpp.hash(&mut hasher);
+            }
+            ClassOperator::Deref { .. } => "deref".hash(&mut hasher),
+        }
+        hasher.finish()
+    }
+}
+
 impl Parse for Class {
     /// Parse the inside of a `cpp_class!` macro.
-    /// Example: `#[derive(Default)] pub unsafe struct Foobar as "FooBar"`
+    /// Example: `#[derive(Default)] pub unsafe struct Foobar as "FooBar"`,
+    /// optionally followed by member functions between braces. The `unsafe`
+    /// keyword may be preceded by `pinned` or `boxed`.
     fn parse(input: ParseStream) -> Result<Self> {
+        let attrs = input.call(Attribute::parse_outer)?;
+        input.parse::<syn::Visibility>()?;
+        let pinned = input.parse::<Option<kw::pinned>>()?.is_some();
+        let boxed = !pinned && input.parse::<Option<kw::boxed>>()?.is_some();
+        input.parse::<Token![unsafe]>()?;
+        input.parse::<Token![struct]>()?;
         Ok(Class {
-            attrs: input.call(Attribute::parse_outer)?,
-            name: {
-                input.parse::<syn::Visibility>()?;
-                input.parse::<Token![unsafe]>()?;
-                input.parse::<Token![struct]>()?;
-                input.parse()?
-            },
+            attrs,
+            pinned,
+            boxed,
+            name: input.parse()?,
             cpp: {
                 input.parse::<Token![as]>()?;
                 input.parse::<syn::LitStr>()?.value()
             },
+            members: {
+                let mut members = Vec::new();
+                if input.peek(syn::token::Brace) {
+                    let content;
+                    braced!(content in input);
+                    while !content.is_empty() {
+                        members.push(content.parse()?);
+                    }
+                }
+                members
+            },
             line: String::new(),
         })
     }
@@ -241,7 +559,7 @@ pub enum Macro {
 }
 
 impl Parse for Macro {
-    ///! Parse the inside of a `cpp!` macro (a literal or a closure)
+    /// Parse the inside of a `cpp!` macro (a literal or a closure)
     fn parse(input: ParseStream) -> Result<Self> {
         if input.peek(syn::token::Brace) {
             let content;
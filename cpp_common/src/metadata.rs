@@ -0,0 +1,115 @@
+//! Reading of the metadata that the generated C++ code stores in the
+//! `rust_cpp_generated` library: the size, alignment and flags of the types
+//! used by the `cpp!` closures and the `cpp_class!` macros.
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use object::read::archive::ArchiveFile;
+use object::{Object, ObjectSection};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+
+use crate::{
+    is_metadata_section, LIB_NAME, MSVC_LIB_NAME, OUT_DIR, STRUCT_METADATA_MAGIC, VERSION,
+};
+
+pub struct MetaData {
+    pub size: usize,
+    pub align: usize,
+    pub flags: u64,
+}
+impl MetaData {
+    pub fn has_flag(&self, f: u32) -> bool {
+        self.flags & (1 << f) != 0
+    }
+}
+
+/// Read the metadata, by hash, from a static library opened with `open_lib_file`.
+pub fn read_metadata(mut file: File) -> io::Result<HashMap<u64, Vec<MetaData>>> {
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    let data = &*data;
+    let archive = ArchiveFile::parse(data).map_err(invalid_data)?;
+
+    // Each object file of the archive may contain a metadata section
+    let mut metadata = HashMap::new();
+    let mut found = false;
+    for member in archive.members() {
+        let member = member.map_err(invalid_data)?;
+        let object = match object::File::parse(member.data(data).map_err(invalid_data)?) {
+            Ok(object) => object,
+            Err(_) => continue, // Not an object file
+        };
+        for section in object.sections() {
+            if section.name().map_or(false, is_metadata_section) {
+                found = true;
+                read_metadata_section(section.data().map_err(invalid_data)?, &mut metadata)?;
+            }
+        }
+    }
+    if !found {
+        return Err(invalid_data(
+            "Struct metadata not present in target library file.\n\
+             NOTE: Double-check that the version of cpp_build and cpp_macros match",
+        ));
+    }
+    Ok(metadata)
+}
+
+/// Read the metadata blobs within the content of a metadata section.
+fn read_metadata_section(
+    mut data: &[u8],
+    metadata: &mut HashMap<u64, Vec<MetaData>>,
+) -> io::Result<()> {
+    // The linker may pad the section with zeros
+    while data.iter().any(|b| *b != 0) {
+        if !data.starts_with(&STRUCT_METADATA_MAGIC) {
+            return Err(invalid_data("Invalid rust-cpp metadata section"));
+        }
+        data = &data[STRUCT_METADATA_MAGIC.len()..];
+
+        // Read & convert the version buffer into a string & compare with our
+        // version.
+        let mut version_buf = [0; 16];
+        data.read_exact(&mut version_buf)?;
+        let version = version_buf
+            .iter()
+            .take_while(|b| **b != b'\0')
+            .map(|b| *b as char)
+            .collect::<String>();
+        if version != VERSION {
+            return Err(invalid_data(
+                "Version mismatch between cpp_macros and cpp_build for same crate.",
+            ));
+        }
+
+        let length = data.read_u64::<LittleEndian>()?;
+        for _ in 0..length {
+            let hash = data.read_u64::<LittleEndian>()?;
+            let size = data.read_u64::<LittleEndian>()? as usize;
+            let align = data.read_u64::<LittleEndian>()? as usize;
+            let flags = data.read_u64::<LittleEndian>()?;
+
+            metadata
+                .entry(hash)
+                .or_default()
+                .push(MetaData { size, align, flags });
+        }
+    }
+    Ok(())
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Try to open a file handle to the lib file. This is used to read the
+/// metadata. We check both `MSVC_LIB_NAME` and `LIB_NAME`, in case we are on
+/// or are targeting Windows.
+pub fn open_lib_file() -> io::Result<File> {
+    if let Ok(file) = File::open(OUT_DIR.join(MSVC_LIB_NAME)) {
+        Ok(file)
+    } else {
+        File::open(OUT_DIR.join(LIB_NAME))
+    }
+}
@@ -10,29 +10,19 @@ extern crate syn;
 extern crate proc_macro;
 use proc_macro2::Span;
 
-use cpp_common::{flags, kw, RustInvocation, FILE_HASH, LIB_NAME, MSVC_LIB_NAME, OUT_DIR, VERSION};
+use cpp_common::metadata::{open_lib_file, read_metadata, MetaData};
+use cpp_common::{
+    flags, kw, ClassOperator, Receiver, RustInvocation, ASSIGN_OPERATORS, BINARY_OPERATORS,
+    FILE_HASH, UNARY_OPERATORS, VERSION,
+};
 use std::collections::HashMap;
 use std::iter::FromIterator;
 use syn::parse::Parser;
 use syn::Ident;
 
-use byteorder::{LittleEndian, ReadBytesExt};
 use if_rust_version::if_rust_version;
 use lazy_static::lazy_static;
 use quote::{quote, quote_spanned};
-use std::fs::File;
-use std::io::{self, BufReader, Read, Seek, SeekFrom};
-
-struct MetaData {
-    size: usize,
-    align: usize,
-    flags: u64,
-}
-impl MetaData {
-    fn has_flag(&self, f: u32) -> bool {
-        self.flags & (1 << f) != 0
-    }
-}
 
 lazy_static! {
     static ref METADATA: HashMap<u64, Vec<MetaData>> = {
@@ -57,77 +47,19 @@ NOTE: Did you make sure to add the rust-cpp build script?
             }
         };
 
-        read_metadata(file).expect(
-            r#"
+        read_metadata(file).unwrap_or_else(|e| {
+            panic!(
+                r#"
 -- rust-cpp fatal error --
 
-I/O error while reading metadata from target library file."#,
-        )
+Error while reading metadata from target library file.
+{}"#,
+                e
+            )
+        })
     };
 }
 
-/// NOTE: This panics when it can produce a better error message
-fn read_metadata(file: File) -> io::Result<HashMap<u64, Vec<MetaData>>> {
-    let mut file = BufReader::new(file);
-    let end = {
-        const AUTO_KEYWORD: &[&[u8]] = &[&cpp_common::STRUCT_METADATA_MAGIC];
-        let aut = aho_corasick::AhoCorasick::new(AUTO_KEYWORD);
-        let found = aut.stream_find_iter(&mut file).next().expect(
-            r#"
--- rust-cpp fatal error --
-
-Struct metadata not present in target library file.
-NOTE: Double-check that the version of cpp_build and cpp_macros match"#,
-        )?;
-        found.end()
-    };
-    file.seek(SeekFrom::Start(end as u64))?;
-
-    // Read & convert the version buffer into a string & compare with our
-    // version.
-    let mut version_buf = [0; 16];
-    file.read_exact(&mut version_buf)?;
-    let version = version_buf
-        .iter()
-        .take_while(|b| **b != b'\0')
-        .map(|b| *b as char)
-        .collect::<String>();
-
-    assert_eq!(
-        version, VERSION,
-        r#"
--- rust-cpp fatal error --
-
-Version mismatch between cpp_macros and cpp_build for same crate."#
-    );
-
-    let length = file.read_u64::<LittleEndian>()?;
-    let mut metadata = HashMap::new();
-    for _ in 0..length {
-        let hash = file.read_u64::<LittleEndian>()?;
-        let size = file.read_u64::<LittleEndian>()? as usize;
-        let align = file.read_u64::<LittleEndian>()? as usize;
-        let flags = file.read_u64::<LittleEndian>()? as u64;
-
-        metadata
-            .entry(hash)
-            .or_insert_with(Vec::new)
-            .push(MetaData { size, align, flags });
-    }
-    Ok(metadata)
-}
-
-/// Try to open a file handle to the lib file. This is used to scan it for
-/// metadata. We check both `MSVC_LIB_NAME` and `LIB_NAME`, in case we are on
-/// or are targeting Windows.
-fn open_lib_file() -> io::Result<File> {
-    if let Ok(file) = File::open(OUT_DIR.join(MSVC_LIB_NAME)) {
-        Ok(file)
-    } else {
-        File::open(OUT_DIR.join(LIB_NAME))
-    }
-}
-
 fn find_all_rust_macro(
     input: syn::parse::ParseStream,
 ) -> Result<Vec<RustInvocation>, syn::parse::Error> {
@@ -156,6 +88,252 @@ fn find_all_rust_macro(
     Ok(r)
 }
 
+/// Returns `T` from the `Result<T, E>` return type of a `try` closure.
+fn result_ok_type(ty: &syn::Type) -> syn::Result<syn::Type> {
+    if let syn::Type::Path(path) = ty {
+        let last = path.path.segments.last().unwrap();
+        if last.ident == "Result" {
+            if let syn::PathArguments::AngleBracketed(ref args) = last.arguments {
+                if let Some(syn::GenericArgument::Type(ok)) = args.args.first() {
+                    return Ok(ok.clone());
+                }
+            }
+        }
+    }
+    Err(syn::Error::new_spanned(
+        ty,
+        "The return type of a `cpp!(try ...)` closure must be `Result<T, cpp::CppException>`",
+    ))
+}
+
+/// Generate the methods constructing, copying and moving a `pinned` class in
+/// place, for the operations supported by the C++ class
+fn pinned_methods(class: &cpp_common::Class, meta: &MetaData) -> proc_macro2::TokenStream {
+    let class_name = &class.name;
+    let hash = class.name_hash();
+    let helper = |name: &str| Ident::new(&format!("__cpp_{}_{}", name, hash), Span::call_site());
+    let mut methods = quote!();
+    if meta.has_flag(flags::IS_DEFAULT_CONSTRUCTIBLE) {
+        let defaultctr_name = helper("default");
+        methods = quote! { #methods
+            /// Construct a default initialized object on the heap.
+            pub fn default_boxed() -> ::core::pin::Pin<::cpp::__Box<Self>> {
+                unsafe {
+                    extern "C" { fn #defaultctr_name(dst: *mut #class_name); }
+                    ::cpp::__pinned_box(|dst| #defaultctr_name(dst))
+                }
+            }
+        };
+    }
+    if meta.has_flag(flags::IS_COPY_CONSTRUCTIBLE) {
+        let copyctr_name = helper("copy");
+        methods = quote! { #methods
+            /// Construct a copy of this object on the heap, with the C++ copy constructor.
+            pub fn clone_boxed(&self) -> ::core::pin::Pin<::cpp::__Box<Self>> {
+                unsafe {
+                    extern "C" { fn #copyctr_name(src: *const #class_name, dst: *mut #class_name); }
+                    ::cpp::__pinned_box(|dst| #copyctr_name(self, dst))
+                }
+            }
+        };
+    }
+    if meta.has_flag(flags::IS_MOVE_CONSTRUCTIBLE) {
+        let move_name = helper("move");
+        methods = quote! { #methods
+            /// Construct an object on the heap with the C++ move constructor, leaving
+            /// this object in its moved-from state.
+            pub fn move_boxed(self: ::core::pin::Pin<&mut Self>) -> ::core::pin::Pin<::cpp::__Box<Self>> {
+                unsafe {
+                    extern "C" { fn #move_name(src: *mut #class_name, dst: *mut #class_name); }
+                    let src = ::core::pin::Pin::get_unchecked_mut(self);
+                    ::cpp::__pinned_box(|dst| #move_name(src, dst))
+                }
+            }
+        };
+    }
+    if meta.has_flag(flags::IS_COPY_ASSIGNABLE) {
+        let copy_assign_name = helper("copy_assign");
+        methods = quote! { #methods
+            /// Assign a copy of `other` to this object, with the C++ copy assignment operator.
+            pub fn assign(self: ::core::pin::Pin<&mut Self>, other: &Self) {
+                unsafe {
+                    extern "C" { fn #copy_assign_name(src: *const #class_name, dst: *mut #class_name); }
+                    #copy_assign_name(other, ::core::pin::Pin::get_unchecked_mut(self))
+                }
+            }
+        };
+    }
+    if meta.has_flag(flags::IS_MOVE_ASSIGNABLE) {
+        let move_assign_name = helper("move_assign");
+        methods = quote! { #methods
+            /// Move `other` into this object with the C++ move assignment operator,
+            /// leaving `other` in its moved-from state.
+            pub fn move_assign(self: ::core::pin::Pin<&mut Self>, other: ::core::pin::Pin<&mut Self>) {
+                unsafe {
+                    extern "C" { fn #move_assign_name(src: *mut #class_name, dst: *mut #class_name); }
+                    #move_assign_name(
+                        ::core::pin::Pin::get_unchecked_mut(other),
+                        ::core::pin::Pin::get_unchecked_mut(self),
+                    )
+                }
+            }
+        };
+    }
+    quote! {
+        impl #class_name {
+            #methods
+        }
+    }
+}
+
+/// Generate the methods of the member functions declared in a `cpp_class!`
+fn expand_members(class: &cpp_common::Class) -> proc_macro2::TokenStream {
+    let class_name = &class.name;
+    let mut methods = quote!();
+    for member in &class.members {
+        let cpp_common::Member {
+            attrs,
+            vis,
+            constructor,
+            name,
+            receiver,
+            args,
+            ret,
+        } = member;
+        let (self_param, self_extern, self_arg) = match receiver {
+            Receiver::None => (quote!(), quote!(), quote!()),
+            Receiver::Ref => (
+                quote!(&self,),
+                quote!(this: *const #class_name,),
+                quote!(self,),
+            ),
+            // A pinned class must not be moved out of a mutable reference
+            Receiver::Mut if class.pinned => (
+                quote!(self: ::core::pin::Pin<&mut Self>,),
+                quote!(this: *mut #class_name,),
+                quote!(::core::pin::Pin::get_unchecked_mut(self),),
+            ),
+            Receiver::Mut => (
+                quote!(&mut self,),
+                quote!(this: *mut #class_name,),
+                quote!(self,),
+            ),
+        };
+        let arg_names: Vec<_> = args.iter().map(|a| &a.name).collect();
+        let arg_types: Vec<_> = args.iter().map(|a| &a.ty).collect();
+        let self_ty: syn::Type = syn::parse_quote!(#class_name);
+        let ret_ty = if *constructor {
+            Some(&self_ty)
+        } else {
+            ret.as_ref().map(|(ty, _)| ty)
+        };
+        let ret_decl = if *constructor && class.pinned {
+            Some(quote!(-> ::core::pin::Pin<::cpp::__Box<Self>>))
+        } else {
+            ret_ty.map(|ty| quote!(-> #ty))
+        };
+
+        let size_data = match METADATA.get(&class.member_hash(member)) {
+            Some(x) => x,
+            None => {
+                #[cfg(not(feature = "docs-only"))]
+                return quote!(compile_error! {
+                    "This cpp_class! member function is not found in the library's rust-cpp metadata."
+                });
+                #[cfg(feature = "docs-only")]
+                {
+                    methods = quote! { #methods
+                        #(#attrs)*
+                        #vis fn #name(#self_param #(#arg_names: #arg_types),*) #ret_decl {
+                            panic!("docs-only")
+                        }
+                    };
+                    continue;
+                }
+            }
+        };
+
+        // The first entry is the return value, followed by the arguments
+        if size_data.len() != args.len() + 1 {
+            let msg = "The rust-cpp metadata of this cpp_class! member function does not match its declaration";
+            let error = syn::Error::new(name.span(), msg).to_compile_error();
+            methods = quote! { #methods #error };
+            continue;
+        }
+
+        if *constructor && !size_data[0].has_flag(flags::IS_CONSTRUCTIBLE) {
+            let cpp_args: Vec<_> = args.iter().map(|a| a.cpp.as_str()).collect();
+            let msg = format!(
+                "C++ class `{}` is not constructible from ({})",
+                class.cpp,
+                cpp_args.join(", ")
+            );
+            let error = syn::Error::new(name.span(), msg).to_compile_error();
+            methods = quote! { #methods #error };
+            continue;
+        }
+
+        if class.pinned && matches!(ret, Some((_, cpp)) if *cpp == class.cpp) {
+            let msg = "A pinned cpp_class! cannot be returned by value";
+            let error = syn::Error::new(name.span(), msg).to_compile_error();
+            methods = quote! { #methods #error };
+            continue;
+        }
+
+        // static assert that the size and alignment of the arguments and of
+        // the return value are the ones of the C++ types
+        let mut assertions = quote!();
+        for (meta, ty) in size_data.iter().skip(1).zip(arg_types.iter()) {
+            let (size, align) = (meta.size, meta.align);
+            assertions = quote! { #assertions
+                const _: [(); #size] = [(); ::core::mem::size_of::<#ty>()];
+                const _: [(); #align] = [(); ::core::mem::align_of::<#ty>()];
+            };
+        }
+        let extern_name = class.member_extern_name(member);
+        let call = match ret_ty {
+            Some(ty) if *constructor && class.pinned => quote! {
+                extern "C" {
+                    fn #extern_name(#(#arg_names: *const #arg_types,)* result: *mut #ty);
+                }
+                ::cpp::__pinned_box(|result| #extern_name(#(&#arg_names,)* result))
+            },
+            Some(ty) => {
+                let (size, align) = (size_data[0].size, size_data[0].align);
+                quote! {
+                    const _: [(); #size] = [(); ::core::mem::size_of::<#ty>()];
+                    const _: [(); #align] = [(); ::core::mem::align_of::<#ty>()];
+                    extern "C" {
+                        fn #extern_name(#self_extern #(#arg_names: *const #arg_types,)* result: *mut #ty);
+                    }
+                    let mut result = ::core::mem::MaybeUninit::<#ty>::uninit();
+                    #extern_name(#self_arg #(&#arg_names,)* result.as_mut_ptr());
+                    result.assume_init()
+                }
+            }
+            None => quote! {
+                extern "C" { fn #extern_name(#self_extern #(#arg_names: *const #arg_types),*); }
+                #extern_name(#self_arg #(&#arg_names),*)
+            },
+        };
+        methods = quote! { #methods
+            #(#attrs)*
+            #vis fn #name(#self_param #(#arg_names: #arg_types),*) #ret_decl {
+                #assertions
+                unsafe { #call }
+            }
+        };
+    }
+    if class.members.is_empty() {
+        return methods;
+    }
+    quote! {
+        impl #class_name {
+            #methods
+        }
+    }
+}
+
 /// Find the occurrence of the `stringify!` macro within the macro derive
 fn extract_original_macro(input: &syn::DeriveInput) -> Option<proc_macro2::TokenStream> {
     #[derive(Default)]
@@ -168,7 +346,7 @@ fn extract_original_macro(input: &syn::DeriveInput) -> Option<proc_macro2::Token
         }
     }
     let mut f = Finder::default();
-    syn::visit::visit_derive_input(&mut f, &input);
+    syn::visit::visit_derive_input(&mut f, input);
     f.0
 }
 
@@ -195,8 +373,8 @@ pub fn expand_internal(input: proc_macro::TokenStream) -> proc_macro::TokenStrea
             #[cfg(not(feature = "docs-only"))]
             return quote!(compile_error! {
 r#"This cpp! macro is not found in the library's rust-cpp metadata.
-NOTE: Only cpp! macros found directly in the program source will be parsed -
-NOTE: They cannot be generated by macro expansion."#})
+NOTE: Only cpp! macros found in the program source, or generated by the
+NOTE: macro_rules! macros defined in the same crate, will be parsed."#})
             .into();
             #[cfg(feature = "docs-only")]
             {
@@ -222,29 +400,35 @@ NOTE: They cannot be generated by macro expansion."#})
         // Generate the assertion to check that the size and align of the types
         // match before calling.
         let MetaData { size, align, .. } = size_data[i + 1];
-        let sizeof_msg = format!(
-            "size_of for argument `{}` does not match between c++ and \
-             rust",
-            &capture.name
-        );
-        let alignof_msg = format!(
-            "align_of for argument `{}` does not match between c++ and \
-             rust",
-            &capture.name
+        let msg = format!(
+            "the type of the argument `{}` does not match the C++ type `{}`, \
+             which has a size of {} and an alignment of {}",
+            &capture.name, &capture.cpp, size, align
         );
+        // The `&str` and `&[u8]` are converted to a `rustcpp::str`
+        let is_str = capture.cpp == "rustcpp::str" || capture.cpp == "std::string_view";
+        let value = if is_str {
+            quote_spanned!(span=> ::cpp::__Str::from($#mac_name))
+        } else {
+            quote_spanned!(span=> $#mac_name)
+        };
         let assertion = quote_spanned! {span=>
-            // Perform a compile time check that the sizes match. This should be
-            // a no-op.
-            if false {
-                ::core::mem::transmute::<_, [u8; #size]>(
-                    ::core::ptr::read(&$#mac_name));
+            {
+                // Perform a compile time check that the layouts match.
+                struct Check<T>(::core::marker::PhantomData<T>);
+                impl<T> Check<T> {
+                    const LAYOUT: () = assert!(
+                        ::core::mem::size_of::<T>() == #size
+                            && ::core::mem::align_of::<T>() == #align,
+                        #msg
+                    );
+                    fn check(_: &T) {
+                        #[allow(clippy::let_unit_value)]
+                        let () = Self::LAYOUT;
+                    }
+                }
+                Check::check(&#value);
             }
-
-            // NOTE: Both of these calls should be dead code in opt builds.
-            assert!(::core::mem::size_of_val(&$#mac_name) == #size,
-                    #sizeof_msg);
-            assert!(::core::mem::align_of_val(&$#mac_name) == #align,
-                    #alignof_msg);
         };
 
         let mb_mut = if capture.mutable {
@@ -262,16 +446,31 @@ NOTE: They cannot be generated by macro expansion."#})
 
         extern_params.push(quote_spanned!(span=> #arg_name : #ptr u8));
 
-        tt_args.push(quote_spanned!(span=> #mb_mut $#mac_name : ident as $#mac_cty : tt));
+        // A `tt` rather than an `ident`, to also accept the name given to a
+        // `macro_rules!` through an `expr` fragment
+        tt_args.push(quote_spanned!(span=> #mb_mut $#mac_name : tt as $#mac_cty : tt));
 
-        call_args.push(quote_spanned!(span=> {
-            #assertion
-            &#mb_mut $#mac_name as #ptr _ as #ptr u8
-        }));
+        // The converted value is a temporary, which must live until the end
+        // of the call, so it is not created within a block
+        call_args.push(quote_spanned!(span=> (
+            #assertion,
+            &#mb_mut #value as #ptr _ as #ptr u8,
+        ).1));
     }
 
     let extern_name = closure.sig.extern_name();
-    let ret_ty = &closure.sig.ret;
+    let is_try = closure.sig.exception;
+    // The type of the value returned by the C++ code: for `try` closures, the
+    // `T` in `Result<T, CppException>`
+    let ret_ty = if is_try {
+        match closure.sig.ret.as_ref().map(result_ok_type) {
+            None => None,
+            Some(Ok(ty)) => Some(ty),
+            Some(Err(err)) => return err.to_compile_error().into(),
+        }
+    } else {
+        closure.sig.ret.clone()
+    };
     let MetaData {
         size: ret_size,
         align: ret_align,
@@ -279,29 +478,66 @@ NOTE: They cannot be generated by macro expansion."#})
     } = size_data[0];
     let is_void = closure.sig.cpp == "void";
 
-    let decl = if is_void {
-        quote! {
-            fn #extern_name(#(#extern_params),*);
-        }
-    } else {
-        quote! {
-            fn #extern_name(#(#extern_params,)* _result: *mut #ret_ty);
-        }
+    if !is_void {
+        extern_params.push(quote!(_result: *mut #ret_ty));
+    }
+    if is_try {
+        extern_params.push(quote! {
+            _exception: *mut ::core::option::Option<::cpp::CppException>,
+            _set_exception: unsafe extern "C" fn(
+                *mut ::core::option::Option<::cpp::CppException>,
+                *const ::core::ffi::c_char,
+                *const ::core::ffi::c_char,
+            )
+        });
+    }
+    let decl = quote! {
+        fn #extern_name(#(#extern_params),*);
     };
 
-    let call = if is_void {
+    let call = if is_try {
+        let exception_args = quote!(&mut exception, ::cpp::__set_cpp_exception);
+        let (result, call, value) = if is_void {
+            assert!(ret_size == 0, "`void` should have a size of 0!");
+            (
+                quote!(),
+                quote!(#extern_name(#(#call_args,)* #exception_args)),
+                quote!(()),
+            )
+        } else {
+            (
+                quote! {
+                    const _ASSERT_SIZE: [(); #ret_size] = [(); ::core::mem::size_of::<#ret_ty>()];
+                    const _ASSERT_ALIGN: [(); #ret_align] = [(); ::core::mem::align_of::<#ret_ty>()];
+                    let mut result = ::core::mem::MaybeUninit::<#ret_ty>::uninit();
+                },
+                quote!(#extern_name(#(#call_args,)* result.as_mut_ptr(), #exception_args)),
+                quote!(result.assume_init()),
+            )
+        };
+        quote! {
+            #result
+            let mut exception = ::core::option::Option::None;
+            #call;
+            match exception {
+                ::core::option::Option::Some(e) => ::core::result::Result::Err(e),
+                ::core::option::Option::None => ::core::result::Result::Ok(#value),
+            }
+        }
+    } else if is_void {
         assert!(ret_size == 0, "`void` should have a size of 0!");
         quote! {
             #extern_name(#(#call_args),*);
-            #[cfg_attr(feature = "cargo-clippy", allow(useless_transmute))]
-            ::core::mem::transmute::<(), (#ret_ty)>(())
+            #[allow(clippy::useless_transmute, clippy::let_and_return)]
+            let result = ::core::mem::transmute::<(), (#ret_ty)>(());
+            result
         }
     } else {
         // static assert that the size and alignement are the same
         let assert_size = quote! {
             if false {
-                const _assert_size: [(); #ret_size] = [(); ::core::mem::size_of::<#ret_ty>()];
-                const _assert_align: [(); #ret_align] = [(); ::core::mem::align_of::<#ret_ty>()];
+                const _ASSERT_SIZE: [(); #ret_size] = [(); ::core::mem::size_of::<#ret_ty>()];
+                const _ASSERT_ALIGN: [(); #ret_align] = [(); ::core::mem::align_of::<#ret_ty>()];
             }
         };
         if_rust_version!(>= 1.36 { quote!(
@@ -385,8 +621,8 @@ pub fn expand_wrap_class(input: proc_macro::TokenStream) -> proc_macro::TokenStr
             #[cfg(not(feature = "docs-only"))]
             return quote!(compile_error! {
 r#"This cpp_class! macro is not found in the library's rust-cpp metadata.
-NOTE: Only cpp_class! macros found directly in the program source will be parsed -
-NOTE: They cannot be generated by macro expansion."#})
+NOTE: Only cpp_class! macros found in the program source, or generated by the
+NOTE: macro_rules! macros defined in the same crate, will be parsed."#})
             .into();
             #[cfg(feature = "docs-only")]
             {
@@ -397,13 +633,23 @@ NOTE: They cannot be generated by macro expansion."#})
                         const ARRAY_SIZE: usize = 1;
                         const CPP_TYPE: &'static str = stringify!(#class_name);
                     }
-                    #[doc = "NOTE: this trait will only be enabled if the C++ underlying type is trivially copyable"]
-                    impl ::core::marker::Copy for #class_name { }
-                    #[doc = "NOTE: this trait will only be enabled if the C++ underlying type is copyable"]
-                    impl ::core::clone::Clone for #class_name {  fn clone(&self) -> Self { panic!("docs-only") } }
-                    #[doc = "NOTE: this trait will only be enabled if the C++ underlying type is default constructible"]
-                    impl ::core::default::Default for #class_name { fn default() -> Self { panic!("docs-only") } }
                 };
+                if !class.pinned && !class.boxed {
+                    result = quote! { #result
+                        #[doc = "NOTE: this trait will only be enabled if the C++ underlying type is trivially copyable"]
+                        impl ::core::marker::Copy for #class_name { }
+                    };
+                }
+                if !class.pinned {
+                    result = quote! { #result
+                        #[doc = "NOTE: this trait will only be enabled if the C++ underlying type is copyable"]
+                        impl ::core::clone::Clone for #class_name {  fn clone(&self) -> Self { panic!("docs-only") } }
+                        #[doc = "NOTE: this trait will only be enabled if the C++ underlying type is default constructible"]
+                        impl ::core::default::Default for #class_name { fn default() -> Self { panic!("docs-only") } }
+                    };
+                }
+                let members = expand_members(&class);
+                result = quote! { #result #members };
                 if class.derives("PartialEq") {
                     result = quote! { #result
                         impl ::core::cmp::PartialEq for #class_name {
@@ -429,6 +675,24 @@ NOTE: They cannot be generated by macro expansion."#})
                         }
                     };
                 }
+                if class.derives("Hash") {
+                    result = quote! { #result
+                        impl ::core::hash::Hash for #class_name {
+                            fn hash<H: ::core::hash::Hasher>(&self, state: &mut H) {
+                                panic!("docs-only")
+                            }
+                        }
+                    };
+                }
+                if class.derives("Debug") {
+                    result = quote! { #result
+                        impl ::core::fmt::Debug for #class_name {
+                            fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+                                panic!("docs-only")
+                            }
+                        }
+                    };
+                }
                 return result.into();
             };
         }
@@ -436,19 +700,33 @@ NOTE: They cannot be generated by macro expansion."#})
 
     let (size, align) = (size_data[0].size, size_data[0].align);
 
+    // The primitive types may be less aligned than their size on some targets,
+    // so the larger alignments use a type with an explicit `repr(align)`
     let base_type = match align {
         1 => quote!(u8),
         2 => quote!(u16),
         4 => quote!(u32),
-        8 => quote!(u64),
-        _ => panic!("unsupported alignment"),
+        _ if align.is_power_of_two() && align <= 1 << 29 => {
+            let ty = Ident::new(&format!("Align{}", align), Span::call_site());
+            quote!(::cpp::__align::#ty)
+        }
+        _ => {
+            let msg = format!("unsupported alignment for a cpp_class!: {}", align);
+            return quote!(compile_error! { #msg }).into();
+        }
     };
 
     let destructor_name = Ident::new(&format!("__cpp_destructor_{}", hash), Span::call_site());
     let copyctr_name = Ident::new(&format!("__cpp_copy_{}", hash), Span::call_site());
     let defaultctr_name = Ident::new(&format!("__cpp_default_{}", hash), Span::call_site());
 
+    let vector_helpers_name =
+        Ident::new(&format!("__cpp_vector_helpers_{}", hash), Span::call_site());
+
     let mut result = quote! {
+        // Check that the layout of the Rust struct is the one of the C++ class
+        const _: [(); #size] = [(); ::core::mem::size_of::<#class_name>()];
+        const _: [(); #align] = [(); ::core::mem::align_of::<#class_name>()];
         #[doc(hidden)]
         impl ::cpp::CppTrait for #class_name {
             type BaseType = #base_type;
@@ -456,6 +734,24 @@ NOTE: They cannot be generated by macro expansion."#})
             const CPP_TYPE: &'static str = stringify!(#class_name);
         }
     };
+    // The elements of a vector are moved when it grows, and the vector of a
+    // boxed class could not be copied
+    if class.derives("VectorElement") {
+        if class.pinned || class.boxed {
+            return quote!(compile_error! {
+                "A pinned or boxed cpp_class! cannot derive VectorElement"
+            })
+            .into();
+        }
+        result = quote! { #result
+            unsafe impl ::cpp::VectorElement for #class_name {
+                fn vector_helpers() -> &'static ::cpp::VectorHelpers {
+                    extern "C" { fn #vector_helpers_name() -> &'static ::cpp::VectorHelpers; }
+                    unsafe { #vector_helpers_name() }
+                }
+            }
+        };
+    }
     if !size_data[0].has_flag(flags::IS_TRIVIALLY_DESTRUCTIBLE) {
         result = quote! { #result
             impl ::core::ops::Drop for #class_name {
@@ -469,7 +765,42 @@ NOTE: They cannot be generated by macro expansion."#})
         };
     };
 
-    if size_data[0].has_flag(flags::IS_COPY_CONSTRUCTIBLE) {
+    if !class.pinned && !class.boxed && !size_data[0].has_flag(flags::IS_MOVE_CONSTRUCTIBLE) {
+        // A class which cannot even be moved by C++ is certainly not relocatable. There is no
+        // stable way to emit a warning from a procedural macro, but the use of a deprecated item
+        // gives one
+        let msg = format!(
+            "The C++ class `{}` cannot be moved, so it is not relocatable. Declare the \
+             cpp_class! as `pinned` or `boxed`",
+            class.cpp
+        );
+        // The span of the class name, since the lints are not reported in external macros
+        let warning = quote_spanned! {class_name.span()=>
+            const _: () = {
+                #[deprecated(note = #msg)]
+                const NOT_RELOCATABLE: () = ();
+                NOT_RELOCATABLE
+            };
+        };
+        result = quote! { #result #warning };
+    }
+    if class.boxed && class.derives("Copy") {
+        return quote!(compile_error! { "A boxed cpp_class! cannot derive Copy" }).into();
+    }
+    if class.pinned {
+        let by_value = ["Clone", "Copy", "Default"]
+            .iter()
+            .chain(BINARY_OPERATORS.iter().map(|(derive, _, _)| derive))
+            .chain(UNARY_OPERATORS.iter().map(|(derive, _, _)| derive));
+        for derive in by_value {
+            if class.derives(derive) {
+                let msg = format!("A pinned cpp_class! cannot derive {}", derive);
+                return quote!(compile_error! { #msg }).into();
+            }
+        }
+        let methods = pinned_methods(&class, &size_data[0]);
+        result = quote! { #result #methods };
+    } else if size_data[0].has_flag(flags::IS_COPY_CONSTRUCTIBLE) {
         if !size_data[0].has_flag(flags::IS_TRIVIALLY_COPYABLE) && !class.derives("Copy") {
             let call_construct = if_rust_version!(>= 1.36 { quote!(
                 let mut result = ::core::mem::MaybeUninit::<Self>::uninit();
@@ -502,7 +833,7 @@ NOTE: They cannot be generated by macro expansion."#})
         panic!("C++ class is not copyable");
     }
 
-    if size_data[0].has_flag(flags::IS_DEFAULT_CONSTRUCTIBLE) {
+    if !class.pinned && size_data[0].has_flag(flags::IS_DEFAULT_CONSTRUCTIBLE) {
         let call_construct = if_rust_version!(>= 1.36 { quote!(
             let mut result = ::core::mem::MaybeUninit::<Self>::uninit();
             #defaultctr_name(result.as_mut_ptr());
@@ -594,12 +925,223 @@ NOTE: They cannot be generated by macro expansion."#})
         };
     }
 
-    if class.derives("Hash") {
-        panic!("Deriving from Hash is not implemented")
+    for (derive, method, _) in BINARY_OPERATORS.iter() {
+        if !class.derives(derive) {
+            continue;
+        }
+        let trait_name = Ident::new(derive, Span::call_site());
+        let method = Ident::new(method, Span::call_site());
+        let helper_name = Ident::new(&format!("__cpp_{}_{}", method, hash), Span::call_site());
+        result = quote! { #result
+            impl<'a> ::core::ops::#trait_name<&'a #class_name> for &'a #class_name {
+                type Output = #class_name;
+                fn #method(self, rhs: &'a #class_name) -> #class_name {
+                    unsafe {
+                        extern "C" {
+                            fn #helper_name(a: *const #class_name, b: *const #class_name, r: *mut #class_name);
+                        }
+                        let mut result = ::core::mem::MaybeUninit::<#class_name>::uninit();
+                        #helper_name(self, rhs, result.as_mut_ptr());
+                        result.assume_init()
+                    }
+                }
+            }
+            impl ::core::ops::#trait_name for #class_name {
+                type Output = #class_name;
+                fn #method(self, rhs: #class_name) -> #class_name {
+                    ::core::ops::#trait_name::#method(&self, &rhs)
+                }
+            }
+        };
+    }
+    for (derive, method, _) in ASSIGN_OPERATORS.iter() {
+        if !class.derives(derive) {
+            continue;
+        }
+        let trait_name = Ident::new(derive, Span::call_site());
+        let method = Ident::new(method, Span::call_site());
+        let helper_name = Ident::new(&format!("__cpp_{}_{}", method, hash), Span::call_site());
+        result = quote! { #result
+            impl<'a> ::core::ops::#trait_name<&'a #class_name> for #class_name {
+                fn #method(&mut self, rhs: &'a #class_name) {
+                    unsafe {
+                        extern "C" { fn #helper_name(a: *mut #class_name, b: *const #class_name); }
+                        #helper_name(self, rhs)
+                    }
+                }
+            }
+            impl ::core::ops::#trait_name for #class_name {
+                fn #method(&mut self, rhs: #class_name) {
+                    ::core::ops::#trait_name::#method(self, &rhs)
+                }
+            }
+        };
+    }
+    for (derive, method, _) in UNARY_OPERATORS.iter() {
+        if !class.derives(derive) {
+            continue;
+        }
+        let trait_name = Ident::new(derive, Span::call_site());
+        let method = Ident::new(method, Span::call_site());
+        let helper_name = Ident::new(&format!("__cpp_{}_{}", method, hash), Span::call_site());
+        result = quote! { #result
+            impl<'a> ::core::ops::#trait_name for &'a #class_name {
+                type Output = #class_name;
+                fn #method(self) -> #class_name {
+                    unsafe {
+                        extern "C" { fn #helper_name(a: *const #class_name, r: *mut #class_name); }
+                        let mut result = ::core::mem::MaybeUninit::<#class_name>::uninit();
+                        #helper_name(self, result.as_mut_ptr());
+                        result.assume_init()
+                    }
+                }
+            }
+            impl ::core::ops::#trait_name for #class_name {
+                type Output = #class_name;
+                fn #method(self) -> #class_name {
+                    ::core::ops::#trait_name::#method(&self)
+                }
+            }
+        };
+    }
+    let operators = match class.operators() {
+        Ok(operators) => operators,
+        Err(err) => return err.to_compile_error().into(),
     };
+    for operator in operators {
+        let op_hash = class.operator_hash(&operator);
+        let op_data = match METADATA.get(&op_hash) {
+            Some(x) => x,
+            None => {
+                return quote!(compile_error! {
+                    "This cpp_class! operator is not found in the library's rust-cpp metadata."
+                })
+                .into()
+            }
+        };
+        // static assert that the size and alignment of the types are the ones
+        // of the C++ types, the first entry is the output, then the index
+        let types = match &operator {
+            ClassOperator::Index { index, output, .. } => vec![output, index],
+            ClassOperator::Deref { target, .. } => vec![target],
+        };
+        if op_data.len() != types.len() {
+            return quote!(compile_error! {
+                "The rust-cpp metadata of this cpp_class! operator does not match its declaration."
+            })
+            .into();
+        }
+        for (meta, ty) in op_data.iter().zip(types) {
+            let (size, align) = (meta.size, meta.align);
+            result = quote! { #result
+                const _: [(); #size] = [(); ::core::mem::size_of::<#ty>()];
+                const _: [(); #align] = [(); ::core::mem::align_of::<#ty>()];
+            };
+        }
+        match operator {
+            ClassOperator::Index {
+                mutable,
+                index,
+                output,
+                ..
+            } => {
+                let index_name = Ident::new(&format!("__cpp_index_{}", op_hash), Span::call_site());
+                result = quote! { #result
+                    impl ::core::ops::Index<#index> for #class_name {
+                        type Output = #output;
+                        fn index(&self, index: #index) -> &#output {
+                            unsafe {
+                                extern "C" {
+                                    fn #index_name(a: *const #class_name, i: *const #index) -> *const #output;
+                                }
+                                &*#index_name(self, &index)
+                            }
+                        }
+                    }
+                };
+                if mutable {
+                    let index_mut_name =
+                        Ident::new(&format!("__cpp_index_mut_{}", op_hash), Span::call_site());
+                    result = quote! { #result
+                        impl ::core::ops::IndexMut<#index> for #class_name {
+                            fn index_mut(&mut self, index: #index) -> &mut #output {
+                                unsafe {
+                                    extern "C" {
+                                        fn #index_mut_name(a: *mut #class_name, i: *const #index) -> *mut #output;
+                                    }
+                                    &mut *#index_mut_name(self, &index)
+                                }
+                            }
+                        }
+                    };
+                }
+            }
+            ClassOperator::Deref {
+                mutable, target, ..
+            } => {
+                let deref_name = Ident::new(&format!("__cpp_deref_{}", op_hash), Span::call_site());
+                result = quote! { #result
+                    impl ::core::ops::Deref for #class_name {
+                        type Target = #target;
+                        fn deref(&self) -> &#target {
+                            unsafe {
+                                extern "C" { fn #deref_name(a: *const #class_name) -> *const #target; }
+                                &*#deref_name(self)
+                            }
+                        }
+                    }
+                };
+                if mutable {
+                    let deref_mut_name =
+                        Ident::new(&format!("__cpp_deref_mut_{}", op_hash), Span::call_site());
+                    result = quote! { #result
+                        impl ::core::ops::DerefMut for #class_name {
+                            fn deref_mut(&mut self) -> &mut #target {
+                                unsafe {
+                                    extern "C" { fn #deref_mut_name(a: *mut #class_name) -> *mut #target; }
+                                    &mut *#deref_mut_name(self)
+                                }
+                            }
+                        }
+                    };
+                }
+            }
+        }
+    }
+
+    if class.derives("Hash") {
+        let hash_name = Ident::new(&format!("__cpp_hash_{}", hash), Span::call_site());
+        result = quote! { #result
+            impl ::core::hash::Hash for #class_name {
+                fn hash<H: ::core::hash::Hasher>(&self, state: &mut H) {
+                    let value = unsafe {
+                        extern "C" { fn #hash_name(a: *const #class_name) -> usize; }
+                        #hash_name(self)
+                    };
+                    state.write_usize(value);
+                }
+            }
+        };
+    }
     if class.derives("Debug") {
-        panic!("Deriving from Debug is not implemented")
-    };
+        let debug_name = Ident::new(&format!("__cpp_debug_{}", hash), Span::call_site());
+        result = quote! { #result
+            impl ::core::fmt::Debug for #class_name {
+                fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+                    unsafe {
+                        extern "C" {
+                            fn #debug_name(a: *const #class_name, f: *mut ::core::ffi::c_void,
+                                callback: ::cpp::DebugCallback);
+                        }
+                        ::cpp::__cpp_debug(f, |f, callback| #debug_name(self, f, callback))
+                    }
+                }
+            }
+        };
+    }
+
+    let members = expand_members(&class);
+    result = quote! { #result #members };
 
     result.into()
 }
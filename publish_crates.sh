@@ -4,4 +4,5 @@ cargo publish --manifest-path cpp_common/Cargo.toml
 cargo publish --manifest-path cpp_build/Cargo.toml
 cargo publish --manifest-path cpp_macros/Cargo.toml
 cargo publish --manifest-path cpp/Cargo.toml
+cargo publish --manifest-path cargo-cpp/Cargo.toml
 
@@ -1,5 +1,13 @@
 extern crate cpp_build;
 
 fn main() {
-    cpp_build::build("src/lib.rs");
+    // The feature of the modules which must not be parsed
+    println!("cargo:rustc-check-cfg=cfg(feature, values(\"non_existent\"))");
+    let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    cpp_build::Config::new()
+        .flag_if_supported("-std=c++17")
+        .panic_policy(cpp_build::PanicPolicy::Rethrow)
+        .compile_commands_dir(out_dir.join("compile_commands"))
+        .mirror_modules(true)
+        .build("src/lib.rs");
 }
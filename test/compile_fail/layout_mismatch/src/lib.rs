@@ -0,0 +1,6 @@
+use cpp::cpp;
+
+// The capture has a size of 8 in Rust but of 4 in C++
+pub fn mismatch(x: i64) -> i32 {
+    unsafe { cpp!([x as "int32_t"] -> i32 as "int32_t" { return x; }) }
+}
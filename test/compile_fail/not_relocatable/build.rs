@@ -0,0 +1,5 @@
+extern crate cpp_build;
+
+fn main() {
+    cpp_build::build("src/lib.rs");
+}
@@ -0,0 +1,14 @@
+// The warning about a class which is not relocatable is denied
+#![deny(deprecated)]
+
+use cpp::{cpp, cpp_class};
+
+cpp! {{
+    // Can be neither copied nor moved
+    struct NotMovable {
+        NotMovable() = default;
+        NotMovable(const NotMovable &) = delete;
+    };
+}}
+
+cpp_class!(pub unsafe struct NotMovable as "NotMovable");
@@ -0,0 +1,10 @@
+use cpp::cpp;
+
+pub fn cfg_test() -> i32 {
+    unsafe {
+        let x_cfg: i32 = 10;
+        cpp! {[x_cfg as "int"] -> i32 as "int" {
+            return x_cfg;
+        }}
+    }
+}
@@ -156,3 +156,337 @@ fn derive_ord() {
     assert!(!(x3 < x3));
     assert!(!(x2 >= x3));
 }
+
+#[test]
+fn derive_debug() {
+    cpp! {{
+        #include <ostream>
+        struct Printable {
+            int value = 42;
+            friend std::ostream &operator<<(std::ostream &os, const Printable &p) {
+                return os << "Printable(" << p.value << ")";
+            }
+        };
+        struct NotPrintable { int value = 1; };
+    }};
+    cpp_class!(#[derive(Debug)] unsafe struct Printable as "Printable");
+    cpp_class!(#[derive(Clone, Debug)] unsafe struct NotPrintable as "NotPrintable");
+
+    assert_eq!(format!("{:?}", Printable::default()), "Printable(42)");
+    let x = NotPrintable::default();
+    assert_eq!(format!("{:?}", x), format!("NotPrintable @ {:p}", &x));
+}
+
+#[test]
+fn derive_hash() {
+    cpp! {{
+        #include <functional>
+        struct Hashable {
+            int value;
+            friend bool operator==(const Hashable &a, const Hashable &b) { return a.value == b.value; }
+        };
+        namespace std {
+        template<> struct hash<Hashable> {
+            size_t operator()(const Hashable &h) const { return std::hash<int>()(h.value); }
+        };
+        }
+    }};
+    cpp_class!(#[derive(PartialEq, Eq, Hash)] unsafe struct Hashable as "Hashable");
+    impl Hashable {
+        fn new(value: i32) -> Self {
+            unsafe { cpp!([value as "int"] -> Hashable as "Hashable" { return Hashable{value}; }) }
+        }
+    }
+
+    let mut map = std::collections::HashMap::new();
+    map.insert(Hashable::new(1), "one");
+    map.insert(Hashable::new(2), "two");
+    map.insert(Hashable::new(1), "uno");
+    assert_eq!(map.len(), 2);
+    assert_eq!(map[&Hashable::new(1)], "uno");
+    assert_eq!(map.get(&Hashable::new(3)), None);
+}
+
+#[test]
+#[allow(clippy::op_ref)]
+fn derive_operators() {
+    cpp! {{
+        struct Vec2 {
+            double x = 0, y = 0;
+            Vec2 operator+(const Vec2 &o) const { return {x + o.x, y + o.y}; }
+            Vec2 operator-(const Vec2 &o) const { return {x - o.x, y - o.y}; }
+            Vec2 operator-() const { return {-x, -y}; }
+            Vec2 &operator*=(const Vec2 &o) { x *= o.x; y *= o.y; return *this; }
+            double &operator[](size_t i) { return i == 0 ? x : y; }
+            const double &operator[](size_t i) const { return i == 0 ? x : y; }
+        };
+        struct Vec2Ptr {
+            Vec2 value;
+            Vec2 &operator*() { return value; }
+            const Vec2 &operator*() const { return value; }
+        };
+    }};
+    cpp_class!(
+        #[derive(Add, Sub, Neg, MulAssign)]
+        #[cpp(index_mut(usize as "size_t") -> f64 as "double")]
+        unsafe struct Vec2 as "Vec2"
+    );
+    cpp_class!(#[cpp(deref_mut -> Vec2 as "Vec2")] unsafe struct Vec2Ptr as "Vec2Ptr");
+
+    let mut a = Vec2::default();
+    a[0] = 1.;
+    a[1] = 2.;
+    let mut b = Vec2::default();
+    b[0] = 10.;
+    b[1] = 20.;
+
+    let c = &a + &b;
+    assert_eq!((c[0], c[1]), (11., 22.));
+    let d = -(b - a);
+    assert_eq!((d[0], d[1]), (-9., -18.));
+    a *= b;
+    assert_eq!((a[0], a[1]), (10., 40.));
+
+    let mut p = Vec2Ptr::default();
+    p[1] = 5.;
+    let v: &Vec2 = &p;
+    assert_eq!(v[1], 5.);
+    assert_eq!((p[0], p[1]), (0., 5.));
+}
+
+#[test]
+fn index_types() {
+    cpp! {{
+        struct Table {
+            double values[3] = {1, 2, 3};
+            const double &operator[](size_t i) const { return values[i]; }
+            const double &operator[](int i) const { return values[i < 0 ? 3 + i : i]; }
+        };
+    }};
+    cpp_class!(
+        #[cpp(index(usize as "size_t") -> f64 as "double")]
+        #[cpp(index(i32 as "int") -> f64 as "double")]
+        unsafe struct Table as "Table"
+    );
+
+    let t = Table::default();
+    assert_eq!(t[1usize], 2.);
+    assert_eq!(t[-1i32], 3.);
+}
+
+#[test]
+fn over_aligned() {
+    cpp! {{
+        struct alignas(32) Aligned32 { float values[8] = {1, 2, 3, 4, 5, 6, 7, 8}; };
+        struct alignas(64) CacheLine { int value = 7; };
+    }};
+    cpp_class!(unsafe struct Aligned32 as "Aligned32");
+    cpp_class!(unsafe struct CacheLine as "CacheLine");
+
+    assert_eq!(std::mem::align_of::<Aligned32>(), 32);
+    assert_eq!(std::mem::size_of::<Aligned32>(), 32);
+    assert_eq!(std::mem::align_of::<CacheLine>(), 64);
+    assert_eq!(std::mem::size_of::<CacheLine>(), 64);
+
+    let values = [Aligned32::default(), Aligned32::default()];
+    for v in &values {
+        assert_eq!(v as *const Aligned32 as usize % 32, 0);
+        let sum = unsafe {
+            cpp!([v as "const Aligned32*"] -> f32 as "float" {
+                float sum = 0;
+                for (float x : v->values)
+                    sum += x;
+                return sum;
+            })
+        };
+        assert_eq!(sum, 36.);
+    }
+    let c = CacheLine::default();
+    let value = unsafe { cpp!([c as "CacheLine"] -> i32 as "int" { return c.value; }) };
+    assert_eq!(value, 7);
+}
+
+#[test]
+fn member_declarations() {
+    cpp! {{
+        struct Counter {
+            int value = 0;
+            int get() const { return value; }
+            void add(int a, int b) { value += a + b; }
+            void reset() { value = 0; }
+            Counter twice() const { Counter c; c.value = value * 2; return c; }
+            static Counter with_value(int value) { Counter c; c.value = value; return c; }
+            Counter() = default;
+            Counter(int a, int b) : value(a * b) {}
+        };
+    }};
+    cpp_class!(
+        #[derive(Clone)]
+        unsafe struct Counter as "Counter" {
+            /// Returns the value
+            pub fn get(&self) -> i32 as "int";
+            fn add(&mut self, a: i32 as "int", b: i32 as "int");
+            fn reset(&mut self);
+            fn twice(&self) -> Counter as "Counter";
+            fn with_value(value: i32 as "int") -> Counter as "Counter";
+            new(a: i32 as "int", b: i32 as "int");
+        }
+    );
+
+    let mut c = Counter::default();
+    assert_eq!(c.get(), 0);
+    c.add(2, 3);
+    assert_eq!(c.get(), 5);
+    assert_eq!(c.twice().get(), 10);
+    c.reset();
+    assert_eq!(c.get(), 0);
+    assert_eq!(Counter::with_value(42).get(), 42);
+    assert_eq!(Counter::new(6, 7).get(), 42);
+}
+
+#[test]
+fn pinned_class() {
+    cpp! {{
+        // Not relocatable: it points to itself
+        struct SelfRef {
+            int value = 0;
+            const SelfRef *self = this;
+            SelfRef() = default;
+            SelfRef(int value) : value(value) {}
+            SelfRef(const SelfRef &o) : value(o.value) {}
+            SelfRef(SelfRef &&o) : value(o.value) { o.value = 0; }
+            SelfRef &operator=(const SelfRef &o) { value = o.value; return *this; }
+            SelfRef &operator=(SelfRef &&o) { value = o.value; o.value = 0; return *this; }
+            bool valid() const { return self == this; }
+            int get() const { return value; }
+            void set(int v) { value = v; }
+        };
+    }};
+    cpp_class!(pinned unsafe struct SelfRef as "SelfRef" {
+        new(value: i32 as "int");
+        fn valid(&self) -> bool as "bool";
+        fn get(&self) -> i32 as "int";
+        fn set(&mut self, v: i32 as "int");
+    });
+
+    let mut a = SelfRef::new(5);
+    assert!(a.valid());
+    assert_eq!(a.get(), 5);
+    a.as_mut().set(6);
+    assert_eq!(a.get(), 6);
+
+    let mut b = a.clone_boxed();
+    assert!(b.valid());
+    assert_eq!(b.get(), 6);
+
+    let c = b.as_mut().move_boxed();
+    assert!(c.valid());
+    assert_eq!(c.get(), 6);
+    assert_eq!(b.get(), 0);
+
+    let mut d = SelfRef::default_boxed();
+    assert!(d.valid());
+    assert_eq!(d.get(), 0);
+    d.as_mut().assign(&c);
+    assert_eq!(d.get(), 6);
+    a.as_mut().set(7);
+    d.as_mut().move_assign(a.as_mut());
+    assert_eq!(d.get(), 7);
+    assert_eq!(a.get(), 0);
+    assert!(a.valid() && d.valid());
+}
+
+#[test]
+fn pinned_class_not_movable() {
+    cpp! {{
+        // Can be neither copied nor moved
+        struct NotMovable {
+            int value = 0;
+            NotMovable() = default;
+            NotMovable(int value) : value(value) {}
+            NotMovable(const NotMovable &) = delete;
+            NotMovable(NotMovable &&) = delete;
+            NotMovable &operator=(const NotMovable &) = delete;
+            NotMovable &operator=(NotMovable &&) = delete;
+            int get() const { return value; }
+            void set(int v) { value = v; }
+        };
+    }};
+    cpp_class!(pinned unsafe struct NotMovable as "NotMovable" {
+        new(value: i32 as "int");
+        fn get(&self) -> i32 as "int";
+        fn set(&mut self, v: i32 as "int");
+    });
+
+    let mut a = NotMovable::new(5);
+    assert_eq!(a.get(), 5);
+    a.as_mut().set(6);
+    assert_eq!(a.get(), 6);
+    assert_eq!(NotMovable::default_boxed().get(), 0);
+}
+
+#[test]
+fn pinned_class_mutex() {
+    cpp! {{
+        #include <mutex>
+    }};
+    cpp_class!(pinned unsafe struct Mutex as "std::mutex" {
+        fn lock(&mut self);
+        fn try_lock(&mut self) -> bool as "bool";
+        fn unlock(&mut self);
+    });
+
+    let mut m = Mutex::default_boxed();
+    m.as_mut().lock();
+    assert!(!m.as_mut().try_lock());
+    m.as_mut().unlock();
+    assert!(m.as_mut().try_lock());
+    m.as_mut().unlock();
+}
+
+#[test]
+#[allow(clippy::op_ref)]
+fn boxed_class() {
+    cpp! {{
+        #include <ostream>
+        struct Big {
+            int values[64] = {};
+            Big() = default;
+            Big(int v) { values[63] = v; }
+            int last() const { return values[63]; }
+            void set_last(int v) { values[63] = v; }
+            Big twice() const { return Big(values[63] * 2); }
+            bool operator==(const Big &o) const { return last() == o.last(); }
+            Big operator+(const Big &o) const { return Big(last() + o.last()); }
+            friend std::ostream &operator<<(std::ostream &s, const Big &b) { return s << "Big(" << b.last() << ")"; }
+        };
+    }};
+    cpp_class!(
+        #[derive(PartialEq, Debug, Add)]
+        boxed unsafe struct Big as "Big" {
+            new(v: i32 as "int");
+            fn last(&self) -> i32 as "int";
+            fn set_last(&mut self, v: i32 as "int");
+            fn twice(&self) -> Big as "rustcpp::boxed<Big>";
+        }
+    );
+
+    assert_eq!(std::mem::size_of::<Big>(), std::mem::size_of::<usize>());
+    let mut a = Big::new(21);
+    assert_eq!(a.last(), 21);
+    assert_eq!(a.twice().last(), 42);
+    let b = a.clone();
+    a.set_last(1);
+    assert_eq!(b.last(), 21);
+    assert_eq!(Big::default().last(), 0);
+    assert_eq!((&a + &b).last(), 22);
+    assert!(a != b);
+    assert_eq!(format!("{:?}", b), "Big(21)");
+
+    let c = unsafe {
+        cpp!([a as "rustcpp::boxed<Big>"] -> Big as "rustcpp::boxed<Big>" {
+            return Big(a->last() + 1);
+        })
+    };
+    assert_eq!(c.last(), 2);
+}
@@ -1,5 +1,14 @@
 #![recursion_limit = "512"]
 #![cfg_attr(not(test), allow(dead_code, unused_imports))]
+// The tests spell out the `return`s, the derived `clone` and the comparisons
+// which clippy would simplify
+#![allow(
+    clippy::needless_return,
+    clippy::assign_op_pattern,
+    clippy::bool_assert_comparison,
+    clippy::clone_on_copy,
+    clippy::nonminimal_bool
+)]
 
 use cpp::{cpp, cpp_class};
 
@@ -23,12 +32,28 @@ mod non_existent;
 #[cfg(feature = "non_existent")]
 mod invalid_code;
 
+// Modules disabled for the current target should not be parsed either
+#[cfg(all(unix, windows))]
+#[path = "invalid_code.rs"]
+mod invalid_code_target;
+
+#[cfg(any(not(any(unix, windows)), all(feature = "non_existent", test)))]
+#[path = "invalid_code.rs"]
+mod invalid_code_complex;
+
+// Modules only enabled with `cfg(test)` are parsed, even if the build script
+// does not know whether we are testing.
+#[cfg(all(test, any(unix, windows)))]
+mod cfg_test;
+
 fn add_two(x: i32) -> i32 {
     x + 2
 }
 
 mod examples;
 
+mod macros;
+
 cpp! {{
     #define _USE_MATH_DEFINES
     #include <math.h>
@@ -54,6 +79,7 @@ cpp! {{
 cpp_class!(
     /// Documentation comments
     /** More /*comments*/ */
+    #[derive(VectorElement)]
     pub unsafe struct A as "A");
 
 impl A {
@@ -216,6 +242,36 @@ fn plusplus() {
     }
 }
 
+#[test]
+fn test_cfg_test() {
+    assert_eq!(cfg_test::cfg_test(), 10);
+}
+
+// Items disabled for the current target should not be parsed
+#[cfg(all(unix, windows))]
+cpp_class!(unsafe struct NotExisting as "NotExisting");
+
+#[cfg(all(unix, windows))]
+impl A {
+    fn invalid_code(&self) {
+        unsafe { cpp!([self as "const A*"] { this is not a valid cpp code! }) }
+    }
+}
+
+#[test]
+fn cfg_items() {
+    #[cfg(all(unix, windows))]
+    let x = unsafe { cpp!([] -> i32 as "int" { this is not a valid cpp code! }) };
+    #[cfg(not(all(unix, windows)))]
+    let x = unsafe { cpp!([] -> i32 as "int" { return 42; }) };
+    assert_eq!(x, 42);
+
+    #[cfg(all(unix, windows))]
+    unsafe {
+        cpp!([] { this is not a valid cpp code! })
+    }
+}
+
 #[test]
 fn test_nomod() {
     assert_eq!(nomod::inner::nomod_inner(), 10);
@@ -340,4 +396,340 @@ fn rust_submacro_closure() {
     assert_eq!(result, 18);
 }
 
+cpp! {{
+    #include <stdexcept>
+    struct NotAnException {};
+}}
+
+#[test]
+fn cpp_exceptions() {
+    let throw_exception = |x: i32| unsafe {
+        cpp!(try [x as "int"] -> Result<u32, cpp::CppException> as "unsigned int" {
+            if (x == 1)
+                throw std::invalid_argument("x is one");
+            if (x == 2)
+                throw NotAnException();
+            return x + 1;
+        })
+    };
+    assert_eq!(throw_exception(0), Ok(1));
+    let e = throw_exception(1).unwrap_err();
+    assert_eq!(e.what(), "x is one");
+    assert_eq!(e.type_name(), "std::invalid_argument");
+    assert_eq!(e.to_string(), "C++ exception std::invalid_argument: x is one");
+    let e = throw_exception(2).unwrap_err();
+    assert_eq!(e.what(), "unknown exception");
+    assert_eq!(e.type_name(), "");
+
+    // Without return type, and with unsafe
+    let mut x = 0;
+    let r = cpp!(unsafe try [mut x as "int"] {
+        x = 42;
+        throw std::runtime_error("void");
+    });
+    assert_eq!(x, 42);
+    assert_eq!(r.unwrap_err().what(), "void");
+    assert!(cpp!(unsafe try [] { }).is_ok());
+
+    // With a class
+    let a = cpp!(unsafe try [] -> Result<A, cpp::CppException> as "A" { return A(4, 5); });
+    assert_eq!(a.unwrap().multiply(), 20);
+}
+
+#[test]
+fn cpp_string() {
+    use cpp::CppString;
+    use std::convert::TryFrom;
+
+    let mut s = CppString::from("hello");
+    assert_eq!(s.len(), 5);
+    assert!(!s.is_empty());
+    assert_eq!(s, "hello");
+    assert_eq!(s.to_str(), Ok("hello"));
+    s.push_str(" world");
+    assert_eq!(s.to_string(), "hello world");
+    assert_eq!(format!("{:?}", s), "\"hello world\"");
+
+    let size = unsafe { cpp!([s as "rustcpp::cpp_string"] -> usize as "size_t" { return s->size(); }) };
+    assert_eq!(size, 11);
+    let s2 = unsafe {
+        cpp!([s as "rustcpp::cpp_string"] -> CppString as "rustcpp::cpp_string" {
+            const std::string &str = s;
+            return str + "!";
+        })
+    };
+    assert_eq!(String::try_from(s2.clone()).unwrap(), "hello world!");
+    assert!(s < s2);
+    assert_ne!(s, s2);
+
+    let invalid = CppString::from(&b"a\xffb"[..]);
+    assert_eq!(invalid.as_bytes(), b"a\xffb");
+    assert!(invalid.to_str().is_err());
+    assert_eq!(invalid.to_string_lossy(), "a\u{fffd}b");
+
+    s.clear();
+    assert!(s.is_empty());
+    assert_eq!(CppString::new(), s);
+    assert_eq!(CppString::from(String::from("x")), "x");
+}
+
+#[test]
+fn cpp_vector() {
+    use cpp::{CppString, CppVector};
+
+    let mut v: CppVector<i32> = (1..=3).collect();
+    v.push(4);
+    assert_eq!(v.len(), 4);
+    assert_eq!(v.as_slice(), &[1, 2, 3, 4]);
+    for x in &mut v {
+        *x *= 10;
+    }
+    v.as_mut_slice()[0] = 5;
+    assert_eq!(v.iter().sum::<i32>(), 95);
+    assert_eq!(format!("{:?}", v), "[5, 20, 30, 40]");
+
+    let sum = unsafe {
+        cpp!([v as "rustcpp::vector<int32_t>"] -> i32 as "int32_t" {
+            int32_t sum = 0;
+            for (int32_t x : v.get())
+                sum += x;
+            return sum;
+        })
+    };
+    assert_eq!(sum, 95);
+    let v2 = unsafe {
+        cpp!([v as "rustcpp::vector<int32_t>"] -> CppVector<i32> as "rustcpp::vector<int32_t>" {
+            std::vector<int32_t> result = v;
+            result.push_back(1);
+            return result;
+        })
+    };
+    assert_eq!(v2.as_slice(), &[5, 20, 30, 40, 1]);
+    assert_eq!(v2.clone(), v2);
+    assert_ne!(v, v2);
+
+    let empty = CppVector::<f64>::new();
+    assert!(empty.is_empty());
+    assert_eq!(empty.as_slice(), &[] as &[f64]);
+
+    // With cpp_class! elements
+    let mut classes = CppVector::with_capacity(2);
+    classes.push(A::new(2, 3));
+    classes.push(A::new(4, 5));
+    assert_eq!(classes.iter().map(A::multiply).collect::<Vec<_>>(), [6, 20]);
+    classes.as_mut_slice()[1].set_values(1, 1);
+    assert_eq!(classes.clone().as_slice()[1].multiply(), 1);
+
+    let strings: CppVector<CppString> = ["a", "b"].iter().map(|&s| CppString::from(s)).collect();
+    let joined = unsafe {
+        cpp!([strings as "rustcpp::vector<rustcpp::cpp_string>"] -> CppString as "rustcpp::cpp_string" {
+            std::string result;
+            for (const std::string &s : strings.get())
+                result += s;
+            return result;
+        })
+    };
+    assert_eq!(joined, "ab");
+}
+
+#[test]
+fn str_captures() {
+    let text = "hello";
+    let bytes: &[u8] = b"abc";
+    let r = unsafe {
+        cpp!([text as "rustcpp::str", bytes as "rustcpp::str"] -> usize as "size_t" {
+            std::string s = text;
+            return s.size() * 10 + bytes.size();
+        })
+    };
+    assert_eq!(r, 53);
+    let starts_with_h = unsafe {
+        cpp!([text as "std::string_view"] -> bool as "bool" {
+            return text.substr(0, 1) == "h" && text.size() == 5;
+        })
+    };
+    assert!(starts_with_h);
+}
+
+#[test]
+fn rust_panic_in_callback() {
+    let call = |x: i32| unsafe {
+        cpp!(try [x as "int"] -> Result<i32, cpp::CppException> as "int" {
+            return rust!(panicCallback [x : i32 as "int"] -> i32 as "int" {
+                if x < 0 {
+                    panic!("negative value: {}", x);
+                }
+                x * 2
+            });
+        })
+    };
+    assert_eq!(call(4), Ok(8));
+    let e = call(-1).unwrap_err();
+    assert_eq!(e.what(), "negative value: -1");
+    assert_eq!(e.type_name(), "rustcpp::rust_panic");
+
+    // The exception can be caught by the C++ code
+    let caught = unsafe {
+        cpp!([] -> bool as "bool" {
+            try {
+                rust!(panicVoidCallback [] { panic!("static message") });
+            } catch (const rustcpp::rust_panic &e) {
+                return std::string(e.what()) == "static message";
+            }
+            return false;
+        })
+    };
+    assert!(caught);
+}
+
+#[test]
+#[cfg(not(target_env = "msvc"))]
+fn rust_panic_escaping_closure() {
+    // The rust_panic exception cannot unwind out of a closure without `try`:
+    // run the test again in a child process, which must be terminated
+    if std::env::var_os("RUST_CPP_ESCAPING_PANIC").is_some() {
+        unsafe {
+            cpp!([] {
+                rust!(panicEscapingCallback [] { panic!("escaping panic") });
+            })
+        };
+        return;
+    }
+    let output = std::process::Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "rust_panic_escaping_closure", "--test-threads=1"])
+        .env("RUST_CPP_ESCAPING_PANIC", "1")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("rustcpp::rust_panic"), "{}", stderr);
+}
+
+#[test]
+fn compile_commands() {
+    // Written by the build script, with a copy of the C++ code of each module
+    let dir = std::path::Path::new(env!("OUT_DIR")).join("compile_commands");
+    let database = std::fs::read_to_string(dir.join("compile_commands.json")).unwrap();
+    assert!(database.contains("cpp_closures.cpp"));
+    assert!(database.contains("src_lib_rs.cpp"));
+    assert!(database.contains("src_cpp_class_rs.cpp"));
+    if !cfg!(target_env = "msvc") {
+        // The flags given to the Config
+        assert!(database.contains("\"-std=c++17\""));
+        assert!(database.contains("\"-DRUST_CPP_RETHROW_PANICS\""));
+    }
+
+    let mirror = std::fs::read_to_string(dir.join("cpp_modules").join("src_lib_rs.cpp")).unwrap();
+    assert!(mirror.contains("return std::string(e.what()) == \"static message\";"));
+    assert!(!mirror.contains("WithOpEq::val"));
+}
+
+fn layout_probe(layout_probe: i32) -> u16 {
+    unsafe { cpp!([layout_probe as "int32_t"] -> u16 as "uint16_t" { return layout_probe; }) }
+}
+
+#[test]
+fn layout_report() {
+    assert_eq!(layout_probe(12), 12);
+
+    // Written by the build script after compiling the library
+    let path = std::path::Path::new(env!("OUT_DIR")).join("rust_cpp/layout.json");
+    let report = std::fs::read_to_string(path).unwrap();
+    assert!(report.starts_with("{\n  \"version\": "));
+    let closure = report
+        .split("\n    {\n")
+        .find(|c| c.contains("\"name\": \"layout_probe\""))
+        .unwrap();
+    assert!(closure.contains("\"file\": \"src/lib.rs\""));
+    let trivial = "\"flags\": [\"copy_constructible\", \"default_constructible\", \
+                   \"trivially_destructible\", \"trivially_copyable\", \
+                   \"trivially_default_constructible\", \"move_constructible\", \
+                   \"copy_assignable\", \"move_assignable\", \"trivially_relocatable\"]";
+    assert!(closure.contains(&format!(
+        "\"return\": {{ \"cpp\": \"uint16_t\", \"size\": 2, \"align\": 2, {} }}",
+        trivial
+    )));
+    assert!(closure.contains(&format!(
+        "{{ \"name\": \"layout_probe\", \"mutable\": false, \"cpp\": \"int32_t\", \
+         \"size\": 4, \"align\": 4, {} }}",
+        trivial
+    )));
+}
+
+#[test]
+fn relocatable_classes() {
+    cpp_class!(unsafe struct UniqueInt as "std::unique_ptr<int>");
+    let ptr = UniqueInt::default();
+    assert!(unsafe { cpp!([ptr as "std::unique_ptr<int>"] -> bool as "bool" { return !ptr; }) });
+
+    let path = std::path::Path::new(env!("OUT_DIR")).join("rust_cpp/layout.json");
+    let report = std::fs::read_to_string(path).unwrap();
+    let flags = |cpp: &str| {
+        let class = report
+            .lines()
+            .find(|l| {
+                l.starts_with("    { \"name\"") && l.contains(&format!("\"cpp\": \"{}\"", cpp))
+            })
+            .unwrap();
+        let flags = &class[class.find("\"flags\"").unwrap()..];
+        (
+            flags.contains("\"trivially_relocatable\""),
+            flags.contains("\"trivially_copyable\""),
+        )
+    };
+    // Detected by rust-cpp
+    assert_eq!(flags("std::unique_ptr<int>"), (true, false));
+    assert_eq!(flags("B"), (true, true));
+    // Not detected, which is not warned about since it can be moved
+    assert_eq!(flags("A"), (false, false));
+    // Points to itself, and thus pinned
+    assert_eq!(flags("SelfRef"), (false, false));
+}
+
+/// Build one of the crates of `compile_fail`, which must fail, and return its
+/// diagnostics
+fn build_compile_fail(name: &str) -> String {
+    let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("compile_fail")
+        .join(name);
+    let output = std::process::Command::new(env!("CARGO"))
+        .arg("build")
+        .arg("--manifest-path")
+        .arg(dir.join("Cargo.toml"))
+        .arg("--target-dir")
+        .arg(std::path::Path::new(env!("OUT_DIR")).join("compile_fail"))
+        .env_remove("CARGO_TARGET_DIR")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn layout_mismatch() {
+    // The crate captures an `i64` as an `int32_t`, which must be reported
+    // when compiling it rather than when calling the closure
+    let stderr = build_compile_fail("layout_mismatch");
+    assert!(
+        stderr.contains(
+            "the type of the argument `x` does not match the C++ type `int32_t`, \
+             which has a size of 4 and an alignment of 4"
+        ),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn not_relocatable_warning() {
+    // The crate denies the warning given for a class which is neither pinned
+    // nor boxed, although it cannot be moved
+    let stderr = build_compile_fail("not_relocatable");
+    assert!(
+        stderr.contains("The C++ class `NotMovable` cannot be moved"),
+        "{}",
+        stderr
+    );
+}
+
 pub mod cpp_class;
@@ -0,0 +1,93 @@
+//! Test that cpp! and cpp_class! macros generated by the macro_rules! macros
+//! of this crate are found by the build script.
+
+use cpp::{cpp, cpp_class};
+
+cpp! {{
+    #include <vector>
+}}
+
+macro_rules! cpp_add {
+    ($name:ident, $x:literal) => {
+        pub fn $name(v: i32) -> i32 {
+            unsafe {
+                cpp!([v as "int"] -> i32 as "int" {
+                    return v + $x;
+                })
+            }
+        }
+    };
+}
+
+cpp_add!(add_one, 1);
+cpp_add!(add_ten, 10);
+
+// A macro expanding to another macro with repetitions
+macro_rules! cpp_adds {
+    ($($name:ident => $x:literal),*) => {
+        $(cpp_add!($name, $x);)*
+    };
+}
+
+cpp_adds!(add_hundred => 100, add_thousand => 1000);
+
+macro_rules! cpp_class_vector {
+    ($name:ident, $ty:literal) => {
+        cpp_class!(pub unsafe struct $name as $ty);
+    };
+}
+
+cpp_class_vector!(IntVector, "std::vector<int>");
+
+impl IntVector {
+    pub fn push(&mut self, v: i32) {
+        unsafe { cpp!([self as "std::vector<int>*", v as "int"] { self->push_back(v); }) }
+    }
+
+    pub fn len(&self) -> usize {
+        unsafe {
+            cpp!([self as "const std::vector<int>*"] -> usize as "size_t" { return self->size(); })
+        }
+    }
+}
+
+#[test]
+fn macro_rules_generated() {
+    assert_eq!(add_one(1), 2);
+    assert_eq!(add_ten(1), 11);
+    assert_eq!(add_hundred(1), 101);
+    assert_eq!(add_thousand(1), 1001);
+
+    let mut v = IntVector::default();
+    v.push(1);
+    v.push(2);
+    assert_eq!(v.len(), 2);
+}
+
+// The `expr` and `ty` fragments are substituted by rustc within invisible
+// groups, which must not change the hash of the closure
+macro_rules! cpp_double {
+    ($e:expr, $t:ty) => {
+        unsafe { cpp!([$e as "int"] -> $t as "int" { return x * 2; }) }
+    };
+}
+
+macro_rules! cpp_return {
+    ($t:ty, $v:expr) => {
+        unsafe { cpp!([] -> $t as "int" { return $v; }) }
+    };
+}
+
+fn double(x: i32) -> i32 {
+    cpp_double!(x, i32)
+}
+
+fn forty_two() -> i32 {
+    cpp_return!(i32, 6 * 7)
+}
+
+#[test]
+fn macro_rules_fragments() {
+    assert_eq!(double(21), 42);
+    assert_eq!(forty_two(), 42);
+}